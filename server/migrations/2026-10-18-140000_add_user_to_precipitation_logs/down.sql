@@ -0,0 +1,4 @@
+DROP INDEX precipitation_logs_user_id_idx;
+
+ALTER TABLE precipitation_logs
+    DROP COLUMN user_id;
@@ -0,0 +1,20 @@
+-- Ties every precipitation log entry to the user that created it.
+ALTER TABLE precipitation_logs
+    ADD COLUMN user_id uuid;
+
+-- Existing entries predate ownership, so they are handed to a single user. Set
+-- `rain_logger.backfill_user_id` (e.g. `ALTER DATABASE rain_logger SET
+-- rain_logger.backfill_user_id = '<uuid>'`) before running this migration to
+-- choose who that is; otherwise the oldest account receives them.
+UPDATE precipitation_logs
+SET user_id = coalesce(
+        nullif(current_setting('rain_logger.backfill_user_id', true), '')::uuid,
+        (SELECT id FROM users ORDER BY created_at LIMIT 1)
+    )
+WHERE user_id IS NULL;
+
+ALTER TABLE precipitation_logs
+    ALTER COLUMN user_id SET NOT NULL,
+    ADD CONSTRAINT precipitation_logs_user_id_fkey FOREIGN KEY (user_id) REFERENCES users (id);
+
+CREATE INDEX precipitation_logs_user_id_idx ON precipitation_logs (user_id);
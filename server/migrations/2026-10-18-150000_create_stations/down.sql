@@ -0,0 +1,6 @@
+DROP INDEX precipitation_logs_station_id_idx;
+
+ALTER TABLE precipitation_logs
+    DROP COLUMN station_id;
+
+DROP TABLE stations;
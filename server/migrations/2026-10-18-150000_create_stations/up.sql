@@ -0,0 +1,38 @@
+-- Creates the stations table. A station is a single rain gauge at a fixed site.
+CREATE TABLE stations
+(
+    id          uuid                     not null primary key,
+    user_id     uuid                     not null,
+    name        varchar                  not null,
+    latitude    double precision,
+    longitude   double precision,
+    elevation   real,
+    time_zone   varchar                  not null default 'UTC',
+    active      boolean                  not null default true,
+    created_at  timestamp with time zone not null default current_timestamp,
+    modified_at timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    UNIQUE (user_id, name),
+    CHECK (latitude BETWEEN -90 AND 90),
+    CHECK (longitude BETWEEN -180 AND 180)
+);
+
+-- Every user that already has entries gets a station to hold them.
+INSERT INTO stations (id, user_id, name)
+SELECT md5(random()::text || clock_timestamp()::text || user_id::text)::uuid, user_id, 'Default'
+FROM (SELECT DISTINCT user_id FROM precipitation_logs) AS owners;
+
+ALTER TABLE precipitation_logs
+    ADD COLUMN station_id uuid;
+
+UPDATE precipitation_logs
+SET station_id = stations.id
+FROM stations
+WHERE stations.user_id = precipitation_logs.user_id
+  AND stations.name = 'Default';
+
+ALTER TABLE precipitation_logs
+    ALTER COLUMN station_id SET NOT NULL,
+    ADD CONSTRAINT precipitation_logs_station_id_fkey FOREIGN KEY (station_id) REFERENCES stations (id);
+
+CREATE INDEX precipitation_logs_station_id_idx ON precipitation_logs (station_id);
@@ -0,0 +1,7 @@
+ALTER TABLE users
+    ADD COLUMN admin boolean not null default false;
+
+-- Someone has to be able to manage accounts; the oldest account becomes the first admin.
+UPDATE users
+SET admin = true
+WHERE id = (SELECT id FROM users ORDER BY created_at LIMIT 1);
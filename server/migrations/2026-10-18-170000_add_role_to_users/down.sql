@@ -0,0 +1,9 @@
+ALTER TABLE users
+    ADD COLUMN admin boolean not null default false;
+
+UPDATE users
+SET admin = true
+WHERE role = 'admin';
+
+ALTER TABLE users
+    DROP COLUMN role;
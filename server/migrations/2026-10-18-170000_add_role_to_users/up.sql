@@ -0,0 +1,11 @@
+-- Replaces the admin flag with a role. Observers record readings, viewers can only read them.
+ALTER TABLE users
+    ADD COLUMN role varchar not null default 'observer'
+        CHECK (role IN ('admin', 'observer', 'viewer'));
+
+UPDATE users
+SET role = 'admin'
+WHERE admin;
+
+ALTER TABLE users
+    DROP COLUMN admin;
@@ -0,0 +1,14 @@
+-- Records every login attempt so repeated failures can lock out an account or address.
+CREATE TABLE login_attempts
+(
+    id         uuid                     not null primary key,
+    user_name  varchar                  not null,
+    user_id    uuid,
+    ip_address varchar,
+    succeeded  boolean                  not null,
+    created_at timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE SET NULL
+);
+
+CREATE INDEX login_attempts_user_name_idx ON login_attempts (user_name, created_at);
+CREATE INDEX login_attempts_ip_address_idx ON login_attempts (ip_address, created_at);
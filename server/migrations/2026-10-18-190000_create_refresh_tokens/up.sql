@@ -0,0 +1,12 @@
+-- Refresh tokens belong to an api token (a login session). Each one can be used once; using it
+-- issues the next token in the chain.
+CREATE TABLE refresh_tokens
+(
+    id           uuid                     not null primary key,
+    api_token_id uuid                     not null,
+    token_hash   varchar                  not null unique,
+    used         boolean                  not null default false,
+    expires_at   timestamp with time zone not null,
+    created_at   timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (api_token_id) REFERENCES api_tokens (id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+ALTER TABLE api_tokens
+    DROP COLUMN user_agent,
+    DROP COLUMN ip_address,
+    DROP COLUMN device_name,
+    DROP COLUMN last_used_at;
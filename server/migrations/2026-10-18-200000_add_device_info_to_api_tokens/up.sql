@@ -0,0 +1,9 @@
+-- Lets users tell their sessions apart when reviewing or revoking them.
+ALTER TABLE api_tokens
+    ADD COLUMN user_agent   varchar,
+    ADD COLUMN ip_address   varchar,
+    ADD COLUMN device_name  varchar,
+    ADD COLUMN last_used_at timestamp with time zone not null default current_timestamp;
+
+UPDATE api_tokens
+SET last_used_at = modified_at;
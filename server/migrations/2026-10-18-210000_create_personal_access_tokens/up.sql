@@ -0,0 +1,15 @@
+-- Long-lived tokens users create for scripts and devices. Only a hash of the secret is kept, and
+-- each token is limited to the scopes it was created with.
+CREATE TABLE personal_access_tokens
+(
+    id           uuid                     not null primary key,
+    user_id      uuid                     not null,
+    name         varchar                  not null,
+    token_hash   varchar                  not null unique,
+    scopes       text[]                   not null,
+    expires_at   timestamp with time zone,
+    last_used_at timestamp with time zone,
+    created_at   timestamp with time zone not null default current_timestamp,
+    UNIQUE (user_id, name),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
@@ -0,0 +1,20 @@
+-- TOTP second factor. A secret without totp_enabled is an enrollment that hasn't been confirmed
+-- yet. totp_last_step is the last time step a code was accepted for, so codes can't be replayed;
+-- 0 means none has been.
+ALTER TABLE users
+    ADD COLUMN totp_secret    varchar,
+    ADD COLUMN totp_enabled   boolean not null default false,
+    ADD COLUMN totp_last_step bigint not null default 0;
+
+-- One-time codes for signing in without the authenticator. Only hashes are stored.
+CREATE TABLE recovery_codes
+(
+    id         uuid                     not null primary key,
+    user_id    uuid                     not null,
+    code_hash  varchar                  not null,
+    used_at    timestamp with time zone,
+    created_at timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
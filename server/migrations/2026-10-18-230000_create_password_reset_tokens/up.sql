@@ -0,0 +1,14 @@
+-- One-time tokens an admin issues so a user can set a new password. Only a hash of the token is
+-- stored.
+CREATE TABLE password_reset_tokens
+(
+    id         uuid                     not null primary key,
+    user_id    uuid                     not null,
+    token_hash varchar                  not null unique,
+    expires_at timestamp with time zone not null,
+    used_at    timestamp with time zone,
+    created_by uuid,
+    created_at timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users (id) ON DELETE SET NULL
+);
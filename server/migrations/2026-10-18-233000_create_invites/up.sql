@@ -0,0 +1,16 @@
+-- Codes admins hand out so people can register themselves. Each can be used max_uses times
+-- before expires_at, and gives the new account its role. Only a hash of the code is stored.
+CREATE TABLE invites
+(
+    id         uuid                     not null primary key,
+    code_hash  varchar                  not null unique,
+    role       varchar                  not null
+        CHECK (role IN ('admin', 'observer', 'viewer')),
+    max_uses   integer                  not null default 1 CHECK (max_uses > 0),
+    uses       integer                  not null default 0,
+    expires_at timestamp with time zone not null,
+    created_by uuid,
+    created_at timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (created_by) REFERENCES users (id) ON DELETE SET NULL,
+    CHECK (uses <= max_uses)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE precipitation_logs
+    DROP CONSTRAINT precipitation_logs_ptype_check;
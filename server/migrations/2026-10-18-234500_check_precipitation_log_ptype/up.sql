@@ -0,0 +1,8 @@
+-- Unknown types used to be read back as unidentified, so store them that way before the
+-- column is constrained to the known ones.
+UPDATE precipitation_logs
+SET ptype = 0
+WHERE ptype NOT BETWEEN 0 AND 3;
+
+ALTER TABLE precipitation_logs
+    ADD CONSTRAINT precipitation_logs_ptype_check CHECK (ptype BETWEEN 0 AND 3);
@@ -0,0 +1,20 @@
+-- Every change made to a precipitation log entry. Rows are only ever added. before and after
+-- hold the entry as JSON, before is null for a create and after is null for a delete. entry_id
+-- has no foreign key so the history outlives the entry.
+CREATE TABLE entry_revisions
+(
+    id         uuid                     not null primary key,
+    entry_id   uuid                     not null,
+    user_id    uuid                     not null,
+    changed_by uuid,
+    action     varchar                  not null
+        CHECK (action IN ('create', 'update', 'delete')),
+    before     jsonb,
+    after      jsonb,
+    created_at timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    FOREIGN KEY (changed_by) REFERENCES users (id) ON DELETE SET NULL,
+    CHECK (before IS NOT NULL OR after IS NOT NULL)
+);
+
+CREATE INDEX entry_revisions_entry_id_idx ON entry_revisions (entry_id, created_at);
@@ -0,0 +1,7 @@
+DROP INDEX precipitation_logs_deleted_idx;
+
+DELETE FROM entry_revisions WHERE action IN ('restore', 'purge');
+ALTER TABLE entry_revisions
+    DROP CONSTRAINT entry_revisions_action_check,
+    ADD CONSTRAINT entry_revisions_action_check
+        CHECK (action IN ('create', 'update', 'delete'));
@@ -0,0 +1,7 @@
+-- Restoring an entry from the trash and purging it for good are recorded as their own actions.
+ALTER TABLE entry_revisions
+    DROP CONSTRAINT entry_revisions_action_check,
+    ADD CONSTRAINT entry_revisions_action_check
+        CHECK (action IN ('create', 'update', 'delete', 'restore', 'purge'));
+
+CREATE INDEX precipitation_logs_deleted_idx ON precipitation_logs (user_id, modified_at) WHERE deleted;
@@ -0,0 +1,16 @@
+-- Responses to writes sent with an Idempotency-Key, so a retry gets the original response back
+-- instead of repeating the write. request_hash identifies the method, path and payload the key
+-- was first used with.
+CREATE TABLE idempotent_requests
+(
+    id           uuid                     not null primary key,
+    user_id      uuid                     not null,
+    key          varchar                  not null,
+    request_hash varchar                  not null,
+    status       integer,
+    body         text,
+    etag         varchar,
+    created_at   timestamp with time zone not null default current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    UNIQUE (user_id, key)
+);
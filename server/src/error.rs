@@ -0,0 +1,231 @@
+use std::error::Error;
+use std::fmt;
+
+use bcrypt::BcryptError;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use log::{debug, error};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket_contrib::json::Json;
+use serde_json::Value;
+
+/// A problem with one field of a request.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Everything that can go wrong handling a request. Routes return it directly; it responds with
+/// the matching status and a JSON body of the form `{"code", "message", "details"}`.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Validation(Vec<FieldError>),
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    NotAcceptable,
+    Conflict(String),
+    PreconditionFailed,
+    PayloadTooLarge,
+    TooManyRequests,
+    Internal(String),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// The JSON an error responds with.
+#[derive(Serialize)]
+pub struct ErrorBody {
+    code: &'static str,
+    message: String,
+    details: Option<Value>,
+}
+
+impl AppError {
+    /// A validation failure on a single field.
+    pub fn invalid(field: &str, message: &str) -> Self {
+        AppError::Validation(vec![FieldError::new(field, message)])
+    }
+
+    /// Several validation failures on the same field.
+    pub fn invalid_field(field: &str, messages: Vec<String>) -> Self {
+        AppError::Validation(messages.iter().map(|m| FieldError::new(field, m)).collect())
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            AppError::BadRequest(_) => Status::BadRequest,
+            AppError::Validation(_) => Status::UnprocessableEntity,
+            AppError::Unauthorized => Status::Unauthorized,
+            AppError::Forbidden => Status::Forbidden,
+            AppError::NotFound => Status::NotFound,
+            AppError::NotAcceptable => Status::NotAcceptable,
+            AppError::Conflict(_) => Status::Conflict,
+            AppError::PreconditionFailed => Status::PreconditionFailed,
+            AppError::PayloadTooLarge => Status::PayloadTooLarge,
+            AppError::TooManyRequests => Status::TooManyRequests,
+            AppError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Unauthorized => "unauthorized",
+            AppError::Forbidden => "forbidden",
+            AppError::NotFound => "not_found",
+            AppError::NotAcceptable => "not_acceptable",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed => "precondition_failed",
+            AppError::PayloadTooLarge => "payload_too_large",
+            AppError::TooManyRequests => "too_many_requests",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let (message, details) = match self {
+            AppError::BadRequest(message) => (message.clone(), None),
+            AppError::Validation(errors) => ("The request has invalid fields".to_string(), serde_json::to_value(errors).ok()),
+            AppError::Unauthorized => ("Authentication is required".to_string(), None),
+            AppError::Forbidden => ("You don't have permission to do that".to_string(), None),
+            AppError::NotFound => ("Not found".to_string(), None),
+            AppError::NotAcceptable => ("None of the accepted formats can be produced".to_string(), None),
+            AppError::Conflict(message) => (message.clone(), None),
+            AppError::PreconditionFailed => ("It has changed since you last read it".to_string(), None),
+            AppError::PayloadTooLarge => ("The request is too large".to_string(), None),
+            AppError::TooManyRequests => ("Too many attempts, try again later".to_string(), None),
+            // The cause is logged, not shown.
+            AppError::Internal(_) => ("Something went wrong".to_string(), None),
+        };
+
+        ErrorBody {
+            code: self.code(),
+            message,
+            details,
+        }
+    }
+
+    /// Logs the error before it's answered. Internal causes are never shown, so they're logged
+    /// as errors.
+    pub fn log(&self) {
+        match self {
+            AppError::Internal(cause) => error!("{}", cause),
+            other => debug!("{}", other),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Internal(cause) => write!(f, "Internal error: {}", cause),
+            other => write!(f, "{}: {}", other.code(), other.body().message),
+        }
+    }
+}
+
+impl Error for AppError {}
+
+impl<'r> Responder<'r> for AppError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        self.log();
+        Response::build_from(Json(self.body()).respond_to(request)?)
+            .status(self.status())
+            .ok()
+    }
+}
+
+impl From<DieselError> for AppError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => AppError::NotFound,
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                AppError::Conflict("Something with those details already exists".to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                AppError::Conflict("Other records still refer to this".to_string())
+            }
+            err => AppError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<BcryptError> for AppError {
+    fn from(err: BcryptError) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
+
+impl From<Box<dyn Error>> for AppError {
+    fn from(err: Box<dyn Error>) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
+
+// Rocket's own failures, such as a guard refusing a request or a body that doesn't parse, are
+// answered with the same JSON shape.
+
+#[catch(400)]
+pub fn bad_request() -> AppError {
+    AppError::BadRequest("The request couldn't be understood".to_string())
+}
+
+#[catch(401)]
+pub fn unauthorized() -> AppError {
+    AppError::Unauthorized
+}
+
+#[catch(403)]
+pub fn forbidden() -> AppError {
+    AppError::Forbidden
+}
+
+#[catch(404)]
+pub fn not_found() -> AppError {
+    AppError::NotFound
+}
+
+#[catch(422)]
+pub fn unprocessable_entity() -> AppError {
+    AppError::Validation(Vec::new())
+}
+
+#[catch(500)]
+pub fn internal_error() -> AppError {
+    AppError::Internal("Unhandled server error".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_diesel_errors() {
+        assert_eq!(Status::NotFound, AppError::from(DieselError::NotFound).status());
+        assert_eq!(Status::InternalServerError, AppError::from(DieselError::RollbackTransaction).status());
+    }
+
+    #[test]
+    fn validation_details_list_fields() {
+        let body = AppError::invalid("measurement", "must not be negative").body();
+        assert_eq!("validation_failed", body.code);
+        assert_eq!(
+            Some(serde_json::json!([{"field": "measurement", "message": "must not be negative"}])),
+            body.details
+        );
+    }
+}
@@ -1,11 +1,19 @@
 #![feature(proc_macro_hygiene, decl_macro)]
+extern crate base32;
+extern crate base64;
 extern crate bcrypt;
 extern crate chrono;
+extern crate chrono_tz;
+extern crate csv;
 #[macro_use]
 extern crate diesel;
 extern crate dotenv;
+extern crate hex;
+extern crate hmac;
 extern crate jsonwebtoken;
 extern crate log;
+extern crate pem;
+extern crate rand;
 #[macro_use]
 extern crate rocket;
 #[macro_use]
@@ -13,10 +21,14 @@ extern crate rocket_contrib;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate sha1;
+extern crate sha2;
+extern crate simple_asn1;
 extern crate uuid;
 
 use dotenv::dotenv;
 
+pub mod error;
 pub mod schema;
 pub mod models;
 pub mod routes;
@@ -33,19 +45,75 @@ pub struct DbConn(diesel::PgConnection);
 fn main() {
     dotenv().ok();
 
+    let lifetimes = utils::jwt::TokenLifetimes::from_env();
+
     rocket::ignite()
         .attach(DbConn::fairing())
+        .manage(models::login_attempt::LoginPolicy::from_env())
+        .manage(utils::password::PasswordPolicy::from_env())
+        .manage(utils::validation::EntryPolicy::from_env())
+        .manage(utils::client::TrustedProxies::from_env())
+        .manage(utils::jwt::Keyring::from_env(lifetimes.access))
+        .manage(lifetimes)
+        .register(catchers![
+            error::bad_request,
+            error::unauthorized,
+            error::forbidden,
+            error::not_found,
+            error::unprocessable_entity,
+            error::internal_error,
+        ])
+        .mount("/", routes![routes::auth::jwks])
         .mount("/api", routes![
             health_check,
             routes::auth::login,
+            routes::auth::login_two_factor,
+            routes::auth::register,
+            routes::auth::refresh,
             routes::auth::logout,
+            routes::auth::logout_all,
+            routes::auth::get_sessions,
+            routes::auth::revoke_session,
+            routes::token::get_tokens,
+            routes::token::create_token,
+            routes::token::revoke_token,
+            routes::two_factor::get_two_factor,
+            routes::two_factor::setup_two_factor,
+            routes::two_factor::enable_two_factor,
+            routes::two_factor::regenerate_recovery_codes,
+            routes::two_factor::disable_two_factor,
             routes::user::get_all_users,
             routes::user::get_user,
+            routes::user::create_user,
+            routes::user::update_user,
+            routes::user::delete_user,
+            routes::user::reset_user_password,
+            routes::password::change_password,
+            routes::password::reset_password,
+            routes::invite::get_invites,
+            routes::invite::create_invite,
+            routes::invite::delete_invite,
             routes::log::get_all_entries,
+            routes::log::export_entries,
+            routes::log::get_summary,
             routes::log::get_entry,
             routes::log::create_entry,
             routes::log::update_entry,
+            routes::log::patch_entry,
             routes::log::delete_entry,
+            routes::log::get_entry_history,
+            routes::log::revert_entry,
+            routes::log::get_trash,
+            routes::log::restore_entry,
+            routes::log::purge_entry,
+            routes::log::purge_trash,
+            routes::import::import_entries,
+            routes::batch::run_batch,
+            routes::station::get_all_stations,
+            routes::station::get_station,
+            routes::station::create_station,
+            routes::station::update_station,
+            routes::station::delete_station,
         ])
         .launch();
 }
@@ -1,11 +1,10 @@
-use std::error::Error;
-
 use chrono::prelude::*;
 use diesel;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use uuid::Uuid;
 
+use crate::error::AppError;
 use crate::models::user::User;
 use crate::schema::api_tokens;
 
@@ -18,10 +17,22 @@ pub struct ApiToken {
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
     pub user_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_name: Option<String>,
+    pub last_used_at: DateTime<Utc>,
+}
+
+/// What we know about the device a session was started from.
+#[derive(Default, Clone)]
+pub struct DeviceInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_name: Option<String>,
 }
 
 impl ApiToken {
-    pub fn create(conn: &PgConnection, token: Self) -> Result<ApiToken, Box<dyn Error>> {
+    pub fn create(conn: &PgConnection, token: Self) -> Result<ApiToken, AppError> {
         diesel::insert_into(api_tokens::table)
             .values(&token)
             .execute(conn)?;
@@ -29,22 +40,82 @@ impl ApiToken {
         Ok(api_tokens::table.filter(api_tokens::id.eq(token.id)).first(conn)?)
     }
 
-    pub fn create_for_user(conn: &PgConnection, user: User) -> Result<ApiToken, Box<dyn Error>> {
+    pub fn create_for_user(conn: &PgConnection, user: User, device: DeviceInfo) -> Result<ApiToken, AppError> {
         let token = ApiToken {
             id: Uuid::new_v4(),
             force_invalid: false,
             created_at: Utc::now(),
             modified_at: Utc::now(),
-            user_id: user.id
+            user_id: user.id,
+            user_agent: device.user_agent,
+            ip_address: device.ip_address,
+            device_name: device.device_name,
+            last_used_at: Utc::now(),
         };
         ApiToken::create(conn, token)
     }
 
-    pub fn get(conn: &PgConnection, id: Uuid) -> Result<ApiToken, Box<dyn Error>> {
+    pub fn get(conn: &PgConnection, id: Uuid) -> Result<ApiToken, AppError> {
         Ok(api_tokens::table.find(id).first(conn)?)
     }
 
-    pub fn invalidate(conn: &PgConnection, id: Uuid) -> Result<(), Box<dyn Error>> {
+    /// Sessions belonging to the user that haven't been revoked and were used since `since`,
+    /// most recently used first.
+    pub fn read_active_for_user(conn: &PgConnection, user_id: Uuid, since: DateTime<Utc>) -> Result<Vec<ApiToken>, AppError> {
+        Ok(api_tokens::table
+            .filter(api_tokens::user_id.eq(user_id))
+            .filter(api_tokens::force_invalid.eq(false))
+            .filter(api_tokens::last_used_at.ge(since))
+            .order(api_tokens::last_used_at.desc())
+            .load::<ApiToken>(conn)?
+        )
+    }
+
+    pub fn touch(conn: &PgConnection, id: Uuid) -> Result<(), AppError> {
+        diesel::update(api_tokens::table)
+            .set(api_tokens::last_used_at.eq(Utc::now()))
+            .filter(api_tokens::id.eq(id))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Revokes one of the user's sessions. Fails with `NotFound` if it belongs to someone else.
+    pub fn invalidate_for_user(conn: &PgConnection, user_id: Uuid, id: Uuid) -> Result<(), AppError> {
+        let update_set = ApiTokenUpdateSet {
+            force_invalid: true,
+            modified_at: Utc::now(),
+        };
+
+        let count = diesel::update(api_tokens::table)
+            .set(&update_set)
+            .filter(api_tokens::id.eq(id))
+            .filter(api_tokens::user_id.eq(user_id))
+            .execute(conn)?;
+
+        match count {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Revokes every session the user has apart from `keep`.
+    pub fn invalidate_others_for_user(conn: &PgConnection, user_id: Uuid, keep: Uuid) -> Result<(), AppError> {
+        let update_set = ApiTokenUpdateSet {
+            force_invalid: true,
+            modified_at: Utc::now(),
+        };
+
+        diesel::update(api_tokens::table)
+            .set(&update_set)
+            .filter(api_tokens::user_id.eq(user_id))
+            .filter(api_tokens::id.ne(keep))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    pub fn invalidate(conn: &PgConnection, id: Uuid) -> Result<(), AppError> {
         let update_set = ApiTokenUpdateSet {
             force_invalid: true,
             modified_at: Utc::now(),
@@ -58,7 +129,7 @@ impl ApiToken {
         Ok(())
     }
 
-    pub fn invalidate_all_for_user(conn: &PgConnection, user: &User) -> Result<(), Box<dyn Error>> {
+    pub fn invalidate_all_for_user(conn: &PgConnection, user: &User) -> Result<(), AppError> {
         let update_set = ApiTokenUpdateSet {
             force_invalid: true,
             modified_at: Utc::now(),
@@ -120,6 +191,10 @@ mod tests {
             created_at: Utc::now(),
             modified_at: Utc::now(),
             user_id: Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap(),
+            user_agent: None,
+            ip_address: None,
+            device_name: Some("Test device".to_string()),
+            last_used_at: Utc::now(),
         };
         ApiToken::create(&connection, token).expect("Failed to create api token.");
         let result = api_tokens::table.find(id).first::<ApiToken>(&connection);
@@ -149,9 +224,8 @@ mod tests {
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL msut be set.");
         let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
         let user_id = Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap();
-        let user_result = User::read(&connection, user_id).unwrap();
-        let user = user_result.get(0).unwrap();
-        ApiToken::invalidate_all_for_user(&connection, user).unwrap();
+        let user = User::read(&connection, user_id).unwrap();
+        ApiToken::invalidate_all_for_user(&connection, &user).unwrap();
         let result = api_tokens::table.filter(api_tokens::user_id.eq(user_id)).load::<ApiToken>(&connection).unwrap();
         for t in result.into_iter() {
             assert_eq!(true, t.force_invalid);
@@ -1,32 +1,97 @@
-use std::error::Error;
+use std::ops::Deref;
 
+use chrono::{Duration, Utc};
 use diesel::pg::PgConnection;
-use log::debug;
+use log::{debug, error};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
-use rocket::Request;
+use rocket::{Request, State};
 use uuid::Uuid;
 
 use crate::DbConn;
+use crate::error::AppError;
 use crate::models::api_token::ApiToken;
+use crate::models::personal_access_token::{PersonalAccessToken, Scope, TOKEN_PREFIX};
 use crate::models::user::User;
-use crate::utils::jwt::read_jwt;
+use crate::utils::jwt::{Keyring, read_jwt};
+
+/// How a request authenticated: a login session's JWT, or a personal access token.
+pub enum Credential {
+    Session(ApiToken),
+    PersonalToken(PersonalAccessToken),
+}
 
 pub struct Auth {
     pub user: User,
-    pub api_token: ApiToken,
+    pub credential: Credential,
 }
 
 impl Auth {
-    pub fn new(conn: &PgConnection, token_id: Uuid) -> Result<Self, Box<dyn Error>> {
+    pub fn new(conn: &PgConnection, token_id: Uuid) -> Result<Self, AppError> {
         let api_token = ApiToken::get(conn, token_id)?;
-        let user = User::read(conn, api_token.user_id)?[0].clone();
+        let user = User::read(conn, api_token.user_id)?;
 
         Ok(Auth {
             user,
-            api_token,
+            credential: Credential::Session(api_token),
         })
     }
+
+    pub fn from_personal_token(conn: &PgConnection, secret: &str) -> Result<Option<Self>, AppError> {
+        let token = match PersonalAccessToken::find_by_secret(conn, secret)? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let user = User::read(conn, token.user_id)?;
+
+        Ok(Some(Auth {
+            user,
+            credential: Credential::PersonalToken(token),
+        }))
+    }
+
+    /// The login session behind the request, if it didn't use a personal access token.
+    pub fn session(&self) -> Option<&ApiToken> {
+        match self.credential {
+            Credential::Session(ref api_token) => Some(api_token),
+            Credential::PersonalToken(_) => None,
+        }
+    }
+
+    /// Whether the request may act with `scope`. Sessions get everything their role allows;
+    /// personal access tokens only what they were created with, and never beyond the role.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        if !scope.allowed_for(self.user.role()) {
+            return false;
+        }
+
+        match self.credential {
+            Credential::Session(_) => true,
+            Credential::PersonalToken(ref token) => token.has_scope(scope),
+        }
+    }
+
+    fn is_revoked(&self) -> bool {
+        match self.credential {
+            Credential::Session(ref api_token) => api_token.force_invalid,
+            Credential::PersonalToken(ref token) => token.is_expired(),
+        }
+    }
+
+    // Only bump the credential's last use now and then so every request doesn't write.
+    fn touch(&self, conn: &PgConnection) -> Result<(), AppError> {
+        let stale = Utc::now() - Duration::minutes(1);
+
+        match self.credential {
+            Credential::Session(ref api_token) if api_token.last_used_at < stale => {
+                ApiToken::touch(conn, api_token.id)
+            }
+            Credential::PersonalToken(ref token) if token.last_used_at.map_or(true, |t| t < stale) => {
+                PersonalAccessToken::touch(conn, token.id)
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for &'a Auth {
@@ -40,28 +105,54 @@ impl<'a, 'r> FromRequest<'a, 'r> for &'a Auth {
             };
 
             let headers = request.headers();
-            let jwts: Vec<_> = headers.get("Authorization").collect();
-            if jwts.len() != 1 {
+            let values: Vec<_> = headers.get("Authorization").collect();
+            if values.len() != 1 {
                 return Err(());
             }
+            let bearer = match values[0].get(7..) {
+                Some(b) => b,
+                None => return Err(()),
+            };
+
+            let auth = if bearer.starts_with(TOKEN_PREFIX) {
+                match Auth::from_personal_token(&conn as &PgConnection, bearer) {
+                    Ok(Some(auth)) => auth,
+                    Ok(None) => return Err(()),
+                    Err(err) => {
+                        error!("{}", err.to_string());
+                        return Err(());
+                    }
+                }
+            } else {
+                let keyring = match request.guard::<State<Keyring>>().succeeded() {
+                    Some(k) => k,
+                    None => return Err(()),
+                };
+                let token_id = match read_jwt(&keyring, bearer) {
+                    Ok(t) => Uuid::parse_str(t.as_str()).unwrap(),
+                    Err(err) => {
+                        debug!("{}", err.to_string());
+                        return Err(());
+                    }
+                };
 
-            let token_id = match read_jwt(&jwts[0][7..]) {
-                Ok(t) => Uuid::parse_str(t.as_str()).unwrap(),
-                Err(err) => {
-                    debug!("{}", err.to_string());
-                    return Err(());
+                match Auth::new(&conn as &PgConnection, token_id) {
+                    Ok(auth) => auth,
+                    Err(_) => return Err(())
                 }
             };
 
-            match Auth::new(&conn as &PgConnection, token_id) {
-                Ok(auth) => Ok(auth),
-                Err(_) => Err(())
+            if let Err(err) = auth.touch(&conn as &PgConnection) {
+                error!("{}", err.to_string());
             }
+
+            Ok(auth)
         });
 
         match auth_result.as_ref() {
             Ok(a) => {
-                if a.api_token.force_invalid {
+                // Disabling a user also invalidates their tokens, but don't rely on that alone.
+                if a.is_revoked() || !a.user.enabled {
                     return Outcome::Failure((Status::Unauthorized, ()));
                 }
 
@@ -71,3 +162,92 @@ impl<'a, 'r> FromRequest<'a, 'r> for &'a Auth {
         }
     }
 }
+
+/// Request guard for routes only administrators may use. Needs the `admin` scope, so any other
+/// role gets a 403.
+pub struct AdminAuth<'a>(pub &'a Auth);
+
+/// Request guard for routes that record or change readings and stations. Needs the `logs:write`
+/// scope, so viewers get a 403.
+pub struct WriterAuth<'a>(pub &'a Auth);
+
+/// Request guard for routes that read readings and stations. Needs the `logs:read` scope.
+pub struct ReaderAuth<'a>(pub &'a Auth);
+
+/// Request guard for managing the login session itself. Personal access tokens get a 403.
+pub struct SessionAuth<'a> {
+    pub user: &'a User,
+    pub api_token: &'a ApiToken,
+}
+
+fn guard_scope<'a, 'r, T>(request: &'a Request<'r>, scope: Scope, wrap: fn(&'a Auth) -> T) -> Outcome<T, ()> {
+    match request.guard::<&Auth>() {
+        Outcome::Success(auth) if auth.has_scope(scope) => Outcome::Success(wrap(auth)),
+        Outcome::Success(_) => Outcome::Failure((Status::Forbidden, ())),
+        Outcome::Failure(f) => Outcome::Failure(f),
+        Outcome::Forward(f) => Outcome::Forward(f),
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminAuth<'a> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        guard_scope(request, Scope::Admin, AdminAuth)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for WriterAuth<'a> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        guard_scope(request, Scope::LogsWrite, WriterAuth)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ReaderAuth<'a> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        guard_scope(request, Scope::LogsRead, ReaderAuth)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for SessionAuth<'a> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        match request.guard::<&Auth>() {
+            Outcome::Success(auth) => match auth.session() {
+                Some(api_token) => Outcome::Success(SessionAuth { user: &auth.user, api_token }),
+                None => Outcome::Failure((Status::Forbidden, ())),
+            },
+            Outcome::Failure(f) => Outcome::Failure(f),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+impl<'a> Deref for AdminAuth<'a> {
+    type Target = Auth;
+
+    fn deref(&self) -> &Auth {
+        self.0
+    }
+}
+
+impl<'a> Deref for WriterAuth<'a> {
+    type Target = Auth;
+
+    fn deref(&self) -> &Auth {
+        self.0
+    }
+}
+
+impl<'a> Deref for ReaderAuth<'a> {
+    type Target = Auth;
+
+    fn deref(&self) -> &Auth {
+        self.0
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::prelude::*;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::precipitation_log::PrecipitationLog;
+use crate::schema::entry_revisions;
+
+/// What a revision did to its entry.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Purge,
+}
+
+impl RevisionAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "create" => Some(RevisionAction::Create),
+            "update" => Some(RevisionAction::Update),
+            "delete" => Some(RevisionAction::Delete),
+            "restore" => Some(RevisionAction::Restore),
+            "purge" => Some(RevisionAction::Purge),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RevisionAction::Create => "create",
+            RevisionAction::Update => "update",
+            RevisionAction::Delete => "delete",
+            RevisionAction::Restore => "restore",
+            RevisionAction::Purge => "purge",
+        }
+    }
+}
+
+/// One change to a precipitation log entry, with the entry as it was before and after. Revisions
+/// are only ever added, never changed or removed, and are written by `PrecipitationLog` itself in
+/// the same transaction as the change.
+#[derive(Serialize, Identifiable, Queryable, Insertable, Clone)]
+#[table_name = "entry_revisions"]
+pub struct EntryRevision {
+    pub id: Uuid,
+    pub entry_id: Uuid,
+    #[serde(skip_serializing)]
+    pub user_id: Uuid,
+    pub changed_by: Option<Uuid>,
+    pub action: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl EntryRevision {
+    /// Builds the revision for a change from `before` to `after`, made by `changed_by`. `before`
+    /// is `None` when the entry wasn't visible until now (create, restore) and `after` when it no
+    /// longer is (delete, purge).
+    pub fn new(action: RevisionAction, changed_by: Uuid, before: Option<&PrecipitationLog>, after: Option<&PrecipitationLog>) -> Result<Self, AppError> {
+        let entry = after.or(before)
+            .ok_or_else(|| AppError::Internal("A revision needs an entry".to_string()))?;
+
+        Ok(EntryRevision {
+            id: Uuid::new_v4(),
+            entry_id: entry.id,
+            user_id: entry.user_id,
+            changed_by: Some(changed_by),
+            action: action.as_str().to_string(),
+            before: before.map(snapshot).transpose()?,
+            after: after.map(snapshot).transpose()?,
+            created_at: Utc::now(),
+        })
+    }
+
+    pub fn record(conn: &PgConnection, action: RevisionAction, changed_by: Uuid, before: Option<&PrecipitationLog>, after: Option<&PrecipitationLog>) -> Result<Self, AppError> {
+        let revision = EntryRevision::new(action, changed_by, before, after)?;
+
+        diesel::insert_into(entry_revisions::table)
+            .values(&revision)
+            .execute(conn)?;
+
+        Ok(revision)
+    }
+
+    /// The history of one of the user's entries, oldest first. It's kept after the entry is gone.
+    pub fn read_for_entry(conn: &PgConnection, user_id: Uuid, entry_id: Uuid) -> Result<Vec<Self>, AppError> {
+        Ok(entry_revisions::table
+            .filter(entry_revisions::entry_id.eq(entry_id))
+            .filter(entry_revisions::user_id.eq(user_id))
+            .order(entry_revisions::created_at.asc())
+            .load::<EntryRevision>(conn)?
+        )
+    }
+
+    pub fn read(conn: &PgConnection, user_id: Uuid, entry_id: Uuid, id: Uuid) -> Result<Option<Self>, AppError> {
+        Ok(entry_revisions::table
+            .filter(entry_revisions::id.eq(id))
+            .filter(entry_revisions::entry_id.eq(entry_id))
+            .filter(entry_revisions::user_id.eq(user_id))
+            .first::<EntryRevision>(conn)
+            .optional()?
+        )
+    }
+
+    /// Whether the entry was ever purged. A purged entry stays gone, whatever its history says.
+    pub fn was_purged(conn: &PgConnection, user_id: Uuid, entry_id: Uuid) -> Result<bool, AppError> {
+        Ok(diesel::select(diesel::dsl::exists(entry_revisions::table
+            .filter(entry_revisions::entry_id.eq(entry_id))
+            .filter(entry_revisions::user_id.eq(user_id))
+            .filter(entry_revisions::action.eq(RevisionAction::Purge.as_str()))
+        )).get_result(conn)?)
+    }
+
+    /// The entry as this revision left it, `None` for a delete or purge.
+    pub fn after_entry(&self) -> Result<Option<PrecipitationLog>, AppError> {
+        match self.after {
+            Some(ref value) => {
+                let mut entry: PrecipitationLog = serde_json::from_value(value.clone())
+                    .map_err(|e| AppError::Internal(format!("Unreadable revision {}: {}", self.id, e)))?;
+                entry.user_id = self.user_id;
+                Ok(Some(entry))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn snapshot(entry: &PrecipitationLog) -> Result<Value, AppError> {
+    serde_json::to_value(entry).map_err(|e| AppError::Internal(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::precipitation_log::PrecipitationType;
+
+    use super::*;
+
+    #[test]
+    fn revisions_keep_both_sides_of_a_change() {
+        let user_id = Uuid::new_v4();
+        let before = PrecipitationLog::new(user_id, Uuid::new_v4(), 1.5, Utc::now(), PrecipitationType::Liquid, None, false);
+        let mut after = before.clone();
+        after.measurement = 2.5;
+        after.notes = Some("Corrected".to_string());
+
+        let created = EntryRevision::new(RevisionAction::Create, user_id, None, Some(&before)).unwrap();
+        assert_eq!(Some(RevisionAction::Create), RevisionAction::parse(&created.action));
+        assert!(created.before.is_none());
+
+        let updated = EntryRevision::new(RevisionAction::Update, user_id, Some(&before), Some(&after)).unwrap();
+        assert_eq!(Some(RevisionAction::Update), RevisionAction::parse(&updated.action));
+        assert_eq!(before.id, updated.entry_id);
+        let restored = updated.after_entry().unwrap().unwrap();
+        assert_eq!(after.measurement, restored.measurement);
+        assert_eq!(after.notes, restored.notes);
+        assert_eq!(after.ptype, restored.ptype);
+        assert_eq!(user_id, restored.user_id);
+
+        let deleted = EntryRevision::new(RevisionAction::Delete, user_id, Some(&after), None).unwrap();
+        assert_eq!(Some(RevisionAction::Delete), RevisionAction::parse(&deleted.action));
+        assert!(deleted.after_entry().unwrap().is_none());
+
+        assert!(EntryRevision::new(RevisionAction::Purge, user_id, None, None).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::schema::idempotent_requests;
+
+/// A write sent with an `Idempotency-Key`, and the response it got. Keys belong to the user that
+/// sent them and are free again after `lifetime`.
+#[derive(Identifiable, Queryable, Insertable, Clone)]
+#[table_name = "idempotent_requests"]
+pub struct IdempotentRequest {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub key: String,
+    pub request_hash: String,
+    pub status: Option<i32>,
+    pub body: Option<String>,
+    pub etag: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl IdempotentRequest {
+    /// Claims `key` for a new request, returning `None`, or returns the earlier request that
+    /// holds it. Run it in the transaction that does the write: a claim by a transaction that's
+    /// still open makes this wait until that one ends, and a rolled back claim frees the key.
+    pub fn claim(conn: &PgConnection, user_id: Uuid, key: &str, request_hash: &str, lifetime: Duration) -> Result<Option<Self>, AppError> {
+        diesel::delete(idempotent_requests::table)
+            .filter(idempotent_requests::user_id.eq(user_id))
+            .filter(idempotent_requests::created_at.lt(Utc::now() - lifetime))
+            .execute(conn)?;
+
+        let request = IdempotentRequest {
+            id: Uuid::new_v4(),
+            user_id,
+            key: key.to_string(),
+            request_hash: request_hash.to_string(),
+            status: None,
+            body: None,
+            etag: None,
+            created_at: Utc::now(),
+        };
+
+        let count = diesel::insert_into(idempotent_requests::table)
+            .values(&request)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        if count == 1 {
+            return Ok(None);
+        }
+
+        Ok(Some(idempotent_requests::table
+            .filter(idempotent_requests::user_id.eq(user_id))
+            .filter(idempotent_requests::key.eq(key))
+            .first::<IdempotentRequest>(conn)?
+        ))
+    }
+
+    /// Stores the response to the request that claimed `key`.
+    pub fn complete(conn: &PgConnection, user_id: Uuid, key: &str, status: i32, body: Option<String>, etag: Option<String>) -> Result<(), AppError> {
+        let count = diesel::update(idempotent_requests::table)
+            .set((
+                idempotent_requests::status.eq(status),
+                idempotent_requests::body.eq(body),
+                idempotent_requests::etag.eq(etag),
+            ))
+            .filter(idempotent_requests::user_id.eq(user_id))
+            .filter(idempotent_requests::key.eq(key))
+            .execute(conn)?;
+
+        match count {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gives up the claim on `key` without storing a response, so it can be used again.
+    pub fn release(conn: &PgConnection, user_id: Uuid, key: &str) -> Result<(), AppError> {
+        diesel::delete(idempotent_requests::table)
+            .filter(idempotent_requests::user_id.eq(user_id))
+            .filter(idempotent_requests::key.eq(key))
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    /*
+     * Run crate::models::user::User::tests::test_create_user() before running these tests!
+     */
+
+    fn test_user_id() -> Uuid {
+        Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn claim_idempotency_key() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let key = Uuid::new_v4().to_string();
+        let first = IdempotentRequest::claim(&connection, test_user_id(), &key, "hash", Duration::hours(24)).expect("Failed to claim key");
+        assert!(first.is_none());
+        IdempotentRequest::complete(&connection, test_user_id(), &key, 200, Some("{}".to_string()), None).expect("Failed to complete request");
+        let second = IdempotentRequest::claim(&connection, test_user_id(), &key, "hash", Duration::hours(24)).expect("Failed to claim key");
+        let stored = second.expect("Key wasn't claimed");
+        assert_eq!(Some(200), stored.status);
+        assert_eq!("hash", stored.request_hash);
+    }
+
+    #[test]
+    #[ignore]
+    fn release_idempotency_key() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let key = Uuid::new_v4().to_string();
+        assert!(IdempotentRequest::claim(&connection, test_user_id(), &key, "hash", Duration::hours(24)).unwrap().is_none());
+        IdempotentRequest::release(&connection, test_user_id(), &key).expect("Failed to release key");
+        assert!(IdempotentRequest::claim(&connection, test_user_id(), &key, "other", Duration::hours(24)).unwrap().is_none());
+    }
+}
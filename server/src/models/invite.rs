@@ -0,0 +1,130 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::user::Role;
+use crate::schema::invites;
+use crate::utils::token::{generate_secret, hash_secret};
+
+/// A code admins hand out so someone can register. Only a hash of the code is stored.
+#[derive(Serialize, Identifiable, Queryable, Insertable, Clone)]
+#[table_name = "invites"]
+pub struct Invite {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+    pub role: String,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Invite {
+    /// Creates an invite, returning it with the code to hand out. The code can't be recovered
+    /// later.
+    pub fn create(conn: &PgConnection, role: Role, max_uses: i32, lifetime: Duration, created_by: Uuid) -> Result<(Self, String), AppError> {
+        let code = generate_secret();
+        let invite = Invite {
+            id: Uuid::new_v4(),
+            code_hash: hash_secret(code.as_str()),
+            role: role.as_str().to_string(),
+            max_uses,
+            uses: 0,
+            expires_at: Utc::now() + lifetime,
+            created_by: Some(created_by),
+            created_at: Utc::now(),
+        };
+
+        diesel::insert_into(invites::table)
+            .values(&invite)
+            .execute(conn)?;
+
+        Ok((invite, code))
+    }
+
+    pub fn read_all(conn: &PgConnection) -> Result<Vec<Self>, AppError> {
+        Ok(invites::table
+            .order(invites::created_at.desc())
+            .load::<Invite>(conn)?
+        )
+    }
+
+    /// Uses up one redemption of the invite with this code, returning it. `None` if there's no
+    /// such invite or it has expired or been used up. Run it in the same transaction as creating
+    /// the user so a failed registration doesn't spend a use.
+    pub fn redeem(conn: &PgConnection, code: &str) -> Result<Option<Self>, AppError> {
+        Ok(diesel::update(invites::table)
+            .set(invites::uses.eq(invites::uses + 1))
+            .filter(invites::code_hash.eq(hash_secret(code)))
+            .filter(invites::uses.lt(invites::max_uses))
+            .filter(invites::expires_at.gt(Utc::now()))
+            .get_result::<Invite>(conn)
+            .optional()?
+        )
+    }
+
+    pub fn delete(conn: &PgConnection, id: Uuid) -> Result<(), AppError> {
+        let count = diesel::delete(invites::table)
+            .filter(invites::id.eq(id))
+            .execute(conn)?;
+
+        match count {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    // The column is constrained to known roles, but if that's ever bypassed fall back to the
+    // least privileged one.
+    pub fn role(&self) -> Role {
+        Role::parse(self.role.as_str()).unwrap_or(Role::Viewer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    /*
+     * These really are integration tests, not unit tests. I've marked them all
+     * ignored as they shouldn't be called during the normal test runs, and only
+     * are useful for working on the database.
+     */
+
+    #[test]
+    #[ignore]
+    fn invite_is_limited_use() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+
+        // YOU MUST UPDATE ID WITH A REAL ID BEFORE TESTING!
+        let admin_id = Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap();
+
+        let (invite, code) = Invite::create(&connection, Role::Observer, 2, Duration::days(1), admin_id).unwrap();
+        assert_eq!(1, Invite::redeem(&connection, &code).unwrap().expect("Invite not redeemed.").uses);
+        assert_eq!(2, Invite::redeem(&connection, &code).unwrap().expect("Invite not redeemed.").uses);
+        assert!(Invite::redeem(&connection, &code).unwrap().is_none());
+
+        Invite::delete(&connection, invite.id).unwrap();
+    }
+}
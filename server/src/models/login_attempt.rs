@@ -0,0 +1,150 @@
+use std::env;
+use std::net::IpAddr;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel;
+use diesel::dsl::max;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::schema::login_attempts;
+
+/// How many failed logins are tolerated before an account or address is locked out, and for
+/// how long. Read from `LOGIN_MAX_FAILURES_PER_ACCOUNT`, `LOGIN_MAX_FAILURES_PER_IP` and
+/// `LOGIN_LOCKOUT_MINUTES`.
+pub struct LoginPolicy {
+    pub max_failures_per_account: i64,
+    pub max_failures_per_ip: i64,
+    pub lockout: Duration,
+}
+
+impl LoginPolicy {
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: i64| env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default);
+
+        Self {
+            max_failures_per_account: read("LOGIN_MAX_FAILURES_PER_ACCOUNT", 5),
+            max_failures_per_ip: read("LOGIN_MAX_FAILURES_PER_IP", 20),
+            lockout: Duration::minutes(read("LOGIN_LOCKOUT_MINUTES", 15)),
+        }
+    }
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone)]
+#[table_name = "login_attempts"]
+pub struct LoginAttempt {
+    pub id: Uuid,
+    pub user_name: String,
+    pub user_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub succeeded: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl LoginAttempt {
+    pub fn record(conn: &PgConnection, user_name: &str, user_id: Option<Uuid>, ip_address: Option<IpAddr>, succeeded: bool) -> Result<(), AppError> {
+        let attempt = LoginAttempt {
+            id: Uuid::new_v4(),
+            user_name: user_name.to_string(),
+            user_id,
+            ip_address: ip_address.map(|ip| ip.to_string()),
+            succeeded,
+            created_at: Utc::now(),
+        };
+
+        diesel::insert_into(login_attempts::table)
+            .values(&attempt)
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Whether further attempts for `user_name` or from `ip_address` should be refused. An
+    /// account's failures stop counting once it logs in successfully; an address' never do until
+    /// they age out of the lockout window.
+    pub fn is_locked_out(conn: &PgConnection, policy: &LoginPolicy, user_name: &str, ip_address: Option<IpAddr>) -> Result<bool, AppError> {
+        let window_start = Utc::now() - policy.lockout;
+
+        let last_success: Option<DateTime<Utc>> = login_attempts::table
+            .select(max(login_attempts::created_at))
+            .filter(login_attempts::user_name.eq(user_name))
+            .filter(login_attempts::succeeded.eq(true))
+            .first(conn)?;
+        let account_since = match last_success {
+            Some(t) if t > window_start => t,
+            _ => window_start,
+        };
+
+        let account_failures: i64 = login_attempts::table
+            .filter(login_attempts::user_name.eq(user_name))
+            .filter(login_attempts::succeeded.eq(false))
+            .filter(login_attempts::created_at.gt(account_since))
+            .count()
+            .get_result(conn)?;
+        if account_failures >= policy.max_failures_per_account {
+            return Ok(true);
+        }
+
+        let ip_address = match ip_address {
+            Some(ip) => ip.to_string(),
+            None => return Ok(false),
+        };
+        let ip_failures: i64 = login_attempts::table
+            .filter(login_attempts::ip_address.eq(ip_address))
+            .filter(login_attempts::succeeded.eq(false))
+            .filter(login_attempts::created_at.gt(window_start))
+            .count()
+            .get_result(conn)?;
+
+        Ok(ip_failures >= policy.max_failures_per_ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    /*
+     * These really are integration tests, not unit tests. I've marked them all
+     * ignored as they shouldn't be called during the normal test runs, and only
+     * are useful for working on the database.
+     */
+
+    #[test]
+    #[ignore]
+    fn lock_out_after_repeated_failures() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let policy = LoginPolicy {
+            max_failures_per_account: 3,
+            max_failures_per_ip: 100,
+            lockout: Duration::minutes(5),
+        };
+        let name = format!("nobody-{}", Uuid::new_v4());
+        for _ in 0..3 {
+            assert!(!LoginAttempt::is_locked_out(&connection, &policy, &name, None).unwrap());
+            LoginAttempt::record(&connection, &name, None, None, false).unwrap();
+        }
+        assert!(LoginAttempt::is_locked_out(&connection, &policy, &name, None).unwrap());
+    }
+}
@@ -2,3 +2,13 @@ pub mod user;
 pub mod api_token;
 pub mod auth;
 pub mod precipitation_log;
+pub mod station;
+pub mod summary;
+pub mod login_attempt;
+pub mod refresh_token;
+pub mod personal_access_token;
+pub mod recovery_code;
+pub mod password_reset_token;
+pub mod invite;
+pub mod entry_revision;
+pub mod idempotent_request;
@@ -0,0 +1,125 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::user::User;
+use crate::schema::password_reset_tokens;
+use crate::utils::token::{generate_secret, hash_secret};
+
+/// A one-time token an admin hands to a user so they can choose a new password. Only a hash of
+/// the secret is stored.
+#[derive(Identifiable, Associations, Queryable, Insertable, Clone)]
+#[belongs_to(User)]
+#[table_name = "password_reset_tokens"]
+pub struct PasswordResetToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PasswordResetToken {
+    /// Creates a reset token for the user, returning it with the secret to pass on. Any earlier
+    /// unused tokens for the user stop working.
+    pub fn issue(conn: &PgConnection, user_id: Uuid, created_by: Uuid, lifetime: Duration) -> Result<(Self, String), AppError> {
+        let secret = generate_secret();
+        let token = PasswordResetToken {
+            id: Uuid::new_v4(),
+            user_id,
+            token_hash: hash_secret(secret.as_str()),
+            expires_at: Utc::now() + lifetime,
+            used_at: None,
+            created_by: Some(created_by),
+            created_at: Utc::now(),
+        };
+
+        conn.transaction::<_, AppError, _>(|| {
+            diesel::delete(password_reset_tokens::table)
+                .filter(password_reset_tokens::user_id.eq(user_id))
+                .filter(password_reset_tokens::used_at.is_null())
+                .execute(conn)?;
+
+            diesel::insert_into(password_reset_tokens::table)
+                .values(&token)
+                .execute(conn)?;
+
+            Ok(())
+        })?;
+
+        Ok((token, secret))
+    }
+
+    /// Finds an unused, unexpired token by its secret.
+    pub fn find_valid(conn: &PgConnection, secret: &str) -> Result<Option<Self>, AppError> {
+        Ok(password_reset_tokens::table
+            .filter(password_reset_tokens::token_hash.eq(hash_secret(secret)))
+            .filter(password_reset_tokens::used_at.is_null())
+            .filter(password_reset_tokens::expires_at.gt(Utc::now()))
+            .first::<PasswordResetToken>(conn)
+            .optional()?
+        )
+    }
+
+    /// Marks the token used. Returns false if it already was.
+    pub fn mark_used(conn: &PgConnection, id: Uuid) -> Result<bool, AppError> {
+        let count = diesel::update(password_reset_tokens::table)
+            .set(password_reset_tokens::used_at.eq(Utc::now()))
+            .filter(password_reset_tokens::id.eq(id))
+            .filter(password_reset_tokens::used_at.is_null())
+            .execute(conn)?;
+
+        Ok(count == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    /*
+     * These really are integration tests, not unit tests. I've marked them all
+     * ignored as they shouldn't be called during the normal test runs, and only
+     * are useful for working on the database.
+     */
+
+    #[test]
+    #[ignore]
+    fn reset_token_is_single_use() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+
+        // YOU MUST UPDATE ID WITH A REAL ID BEFORE TESTING!
+        let user_id = Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap();
+
+        let (first, first_secret) = PasswordResetToken::issue(&connection, user_id, user_id, Duration::hours(1)).unwrap();
+        let (second, second_secret) = PasswordResetToken::issue(&connection, user_id, user_id, Duration::hours(1)).unwrap();
+        assert!(PasswordResetToken::find_valid(&connection, &first_secret).unwrap().is_none());
+
+        let found = PasswordResetToken::find_valid(&connection, &second_secret).unwrap().expect("Token not found.");
+        assert_eq!(second.id, found.id);
+        assert_ne!(first.id, found.id);
+        assert!(PasswordResetToken::mark_used(&connection, found.id).unwrap());
+        assert!(!PasswordResetToken::mark_used(&connection, found.id).unwrap());
+    }
+}
@@ -0,0 +1,218 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::user::{Role, User};
+use crate::schema::personal_access_tokens;
+use crate::utils::token::{generate_secret, hash_secret};
+
+/// Marks a bearer token as a personal access token rather than a session JWT.
+pub const TOKEN_PREFIX: &str = "rlpat_";
+
+/// What a personal access token may be used for.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Scope {
+    #[serde(rename = "logs:read")]
+    LogsRead,
+    #[serde(rename = "logs:write")]
+    LogsWrite,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "logs:read" => Some(Scope::LogsRead),
+            "logs:write" => Some(Scope::LogsWrite),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::LogsRead => "logs:read",
+            Scope::LogsWrite => "logs:write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Whether a user with `role` may hold this scope at all. A token never grants more than
+    /// its owner's role does.
+    pub fn allowed_for(&self, role: Role) -> bool {
+        match self {
+            Scope::LogsRead => true,
+            Scope::LogsWrite => role.can_write(),
+            Scope::Admin => role == Role::Admin,
+        }
+    }
+}
+
+#[derive(Serialize, Identifiable, Associations, Queryable, Insertable, Clone)]
+#[belongs_to(User)]
+#[table_name = "personal_access_tokens"]
+pub struct PersonalAccessToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PersonalAccessToken {
+    /// Creates a token for the user, returning it with the secret to give them. The secret can't
+    /// be recovered later.
+    pub fn create(conn: &PgConnection, user_id: Uuid, name: String, scopes: &[Scope], lifetime: Option<Duration>) -> Result<(Self, String), AppError> {
+        let secret = format!("{}{}", TOKEN_PREFIX, generate_secret());
+        let token = PersonalAccessToken {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            token_hash: hash_secret(secret.as_str()),
+            scopes: scopes.iter().map(|s| s.as_str().to_string()).collect(),
+            expires_at: lifetime.map(|l| Utc::now() + l),
+            last_used_at: None,
+            created_at: Utc::now(),
+        };
+
+        diesel::insert_into(personal_access_tokens::table)
+            .values(&token)
+            .execute(conn)?;
+
+        Ok((token, secret))
+    }
+
+    pub fn read_all(conn: &PgConnection, user_id: Uuid) -> Result<Vec<Self>, AppError> {
+        Ok(personal_access_tokens::table
+            .filter(personal_access_tokens::user_id.eq(user_id))
+            .order(personal_access_tokens::created_at.desc())
+            .load::<PersonalAccessToken>(conn)?
+        )
+    }
+
+    pub fn find_by_secret(conn: &PgConnection, secret: &str) -> Result<Option<Self>, AppError> {
+        Ok(personal_access_tokens::table
+            .filter(personal_access_tokens::token_hash.eq(hash_secret(secret)))
+            .first::<PersonalAccessToken>(conn)
+            .optional()?
+        )
+    }
+
+    pub fn touch(conn: &PgConnection, id: Uuid) -> Result<(), AppError> {
+        diesel::update(personal_access_tokens::table)
+            .set(personal_access_tokens::last_used_at.eq(Utc::now()))
+            .filter(personal_access_tokens::id.eq(id))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    pub fn delete(conn: &PgConnection, user_id: Uuid, id: Uuid) -> Result<(), AppError> {
+        let count = diesel::delete(personal_access_tokens::table)
+            .filter(personal_access_tokens::id.eq(id))
+            .filter(personal_access_tokens::user_id.eq(user_id))
+            .execute(conn)?;
+
+        match count {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes every token the user has, as when their password changes.
+    pub fn revoke_all_for_user(conn: &PgConnection, user_id: Uuid) -> Result<(), AppError> {
+        diesel::delete(personal_access_tokens::table)
+            .filter(personal_access_tokens::user_id.eq(user_id))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| s == scope.as_str())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map_or(false, |e| e < Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    #[test]
+    fn scopes_are_limited_by_role() {
+        assert!(Scope::LogsRead.allowed_for(Role::Viewer));
+        assert!(!Scope::LogsWrite.allowed_for(Role::Viewer));
+        assert!(Scope::LogsWrite.allowed_for(Role::Observer));
+        assert!(!Scope::Admin.allowed_for(Role::Observer));
+        assert!(Scope::Admin.allowed_for(Role::Admin));
+    }
+
+    /*
+     * These really are integration tests, not unit tests. I've marked them all
+     * ignored as they shouldn't be called during the normal test runs, and only
+     * are useful for working on the database.
+     */
+
+    #[test]
+    #[ignore]
+    fn create_and_find_token() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+
+        // YOU MUST UPDATE ID WITH A REAL ID BEFORE TESTING!
+        let user_id = Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap();
+        let name = format!("gauge-{}", Uuid::new_v4());
+
+        let (token, secret) = PersonalAccessToken::create(&connection, user_id, name, &[Scope::LogsWrite], None).unwrap();
+        assert!(secret.starts_with(TOKEN_PREFIX));
+        let found = PersonalAccessToken::find_by_secret(&connection, &secret).unwrap().expect("Token not found.");
+        assert_eq!(token.id, found.id);
+        assert!(found.has_scope(Scope::LogsWrite));
+        assert!(!found.has_scope(Scope::LogsRead));
+
+        PersonalAccessToken::delete(&connection, user_id, token.id).unwrap();
+        assert!(PersonalAccessToken::find_by_secret(&connection, &secret).unwrap().is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn revoke_all_tokens_for_user() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+
+        // YOU MUST UPDATE ID WITH A REAL ID BEFORE TESTING!
+        let user_id = Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap();
+        let (_, secret) = PersonalAccessToken::create(&connection, user_id, "gauge".to_string(), &[Scope::LogsRead], None).unwrap();
+
+        PersonalAccessToken::revoke_all_for_user(&connection, user_id).unwrap();
+        assert!(PersonalAccessToken::find_by_secret(&connection, &secret).unwrap().is_none());
+        assert!(PersonalAccessToken::read_all(&connection, user_id).unwrap().is_empty());
+    }
+}
@@ -1,14 +1,25 @@
-use std::error::Error;
+use std::io::Write;
 
 use chrono::prelude::*;
 use diesel;
-use diesel::pg::PgConnection;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgConnection};
 use diesel::prelude::*;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::SmallInt;
 use uuid::Uuid;
 
+use crate::error::AppError;
+use crate::models::entry_revision::{EntryRevision, RevisionAction};
+use crate::models::station::Station;
+use crate::models::user::User;
 use crate::schema::precipitation_logs;
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+/// What fell. The API uses the names; the database stores the discriminants in a smallint that's
+/// constrained to them.
+#[derive(Serialize, Deserialize, AsExpression, FromSqlRow, PartialEq, Eq, Copy, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+#[sql_type = "SmallInt"]
 pub enum PrecipitationType {
     Unidentified = 0,
     Liquid = 1,
@@ -18,84 +29,273 @@ pub enum PrecipitationType {
 
 impl PrecipitationType {
     // So dumb that casting enums still isn't a thing without a crate or code like this.
-    pub fn from_i16(value: i16) -> Self {
+    pub fn from_i16(value: i16) -> Option<Self> {
+        match value {
+            0 => Some(PrecipitationType::Unidentified),
+            1 => Some(PrecipitationType::Liquid),
+            2 => Some(PrecipitationType::Freezing),
+            3 => Some(PrecipitationType::Frozen),
+            _ => None,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
         match value {
-            1 => PrecipitationType::Liquid,
-            2 => PrecipitationType::Freezing,
-            3 => PrecipitationType::Frozen,
-            _ => PrecipitationType::Unidentified,
+            "unidentified" => Some(PrecipitationType::Unidentified),
+            "liquid" => Some(PrecipitationType::Liquid),
+            "freezing" => Some(PrecipitationType::Freezing),
+            "frozen" => Some(PrecipitationType::Frozen),
+            _ => None,
         }
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrecipitationType::Unidentified => "unidentified",
+            PrecipitationType::Liquid => "liquid",
+            PrecipitationType::Freezing => "freezing",
+            PrecipitationType::Frozen => "frozen",
+        }
+    }
+}
+
+impl ToSql<SmallInt, Pg> for PrecipitationType {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<SmallInt, Pg>::to_sql(&(*self as i16), out)
+    }
+}
+
+impl FromSql<SmallInt, Pg> for PrecipitationType {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let value = <i16 as FromSql<SmallInt, Pg>>::from_sql(bytes)?;
+        PrecipitationType::from_i16(value).ok_or_else(|| format!("Unknown precipitation type {}", value).into())
+    }
+}
+
+/// Narrows down which entries a search returns. Every field is optional; `from` is inclusive and
+/// `to` is exclusive.
+#[derive(Clone, Default)]
+pub struct EntryFilter {
+    pub station_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub ptype: Option<PrecipitationType>,
+    pub anomaly: Option<bool>,
+    pub min_measurement: Option<f32>,
+    pub max_measurement: Option<f32>,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
 }
 
-#[derive(Serialize, Deserialize, Identifiable, AsChangeset, Queryable, Insertable, Clone)]
+/// Position of the last entry on a page. Entries are ordered by `logged_at` with `id` as the tie
+/// breaker, so the pair uniquely identifies where the next page starts.
+#[derive(PartialEq, Clone, Debug)]
+pub struct EntryCursor {
+    pub logged_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl EntryCursor {
+    pub fn for_entry(entry: &PrecipitationLog) -> Self {
+        Self {
+            logged_at: entry.logged_at,
+            id: entry.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}_{}", micros(&self.logged_at), self.id.to_simple())
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(2, '_');
+        let micros: i64 = parts.next()?.parse().ok()?;
+        let id = Uuid::parse_str(parts.next()?).ok()?;
+        let logged_at = Utc.timestamp_opt(
+            micros.div_euclid(1_000_000),
+            (micros.rem_euclid(1_000_000) * 1_000) as u32,
+        ).single()?;
+
+        Some(Self {
+            logged_at,
+            id,
+        })
+    }
+}
+
+// Postgres only keeps microseconds, so that's all the precision cursors and tags need.
+fn micros(time: &DateTime<Utc>) -> i64 {
+    time.timestamp() * 1_000_000 + i64::from(time.timestamp_subsec_micros())
+}
+
+#[derive(Serialize, Deserialize, Identifiable, Associations, AsChangeset, Queryable, Insertable, Clone)]
+#[belongs_to(User)]
+#[belongs_to(Station)]
 #[table_name = "precipitation_logs"]
 pub struct PrecipitationLog {
     pub id: Uuid,
     pub measurement: f32,
     pub logged_at: DateTime<Utc>,
     pub notes: Option<String>,
-    pub ptype: i16,
+    pub ptype: PrecipitationType,
     pub anomaly: bool,
 
     #[serde(skip_serializing, skip_deserializing)]
     pub deleted: bool,
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
+
+    // Ownership always comes from the authenticated user, never from the request body.
+    #[serde(skip_deserializing)]
+    pub user_id: Uuid,
+    pub station_id: Uuid,
 }
 
 impl PrecipitationLog {
-    pub fn new(measurement: f32, logged_at: DateTime<Utc>, ptype: PrecipitationType, notes: Option<String>, anomaly: bool) -> Self {
-        let integer_type = ptype as i16;
-
+    pub fn new(user_id: Uuid, station_id: Uuid, measurement: f32, logged_at: DateTime<Utc>, ptype: PrecipitationType, notes: Option<String>, anomaly: bool) -> Self {
         Self {
             id: Uuid::new_v4(),
             measurement,
             logged_at,
             notes,
-            ptype: integer_type,
+            ptype,
             anomaly,
             deleted: false,
             created_at: Utc::now(),
             modified_at: Utc::now(),
+            user_id,
+            station_id,
         }
     }
 
-    fn new_for_upsert(db_entry: &Self, user_entry: &Self) -> Self {
+    /// This entry with its readings replaced by those in `changes`. Identity, ownership and
+    /// `created_at` stay as they are.
+    pub fn replaced_by(&self, changes: &Self) -> Self {
         Self {
-            id: db_entry.id.clone(),
-            measurement: user_entry.measurement,
-            logged_at: user_entry.logged_at,
-            notes: user_entry.notes.to_owned(),
-            ptype: user_entry.ptype,
-            anomaly: user_entry.anomaly,
-            deleted: false,
-            created_at: db_entry.created_at,
+            id: self.id,
+            measurement: changes.measurement,
+            logged_at: changes.logged_at,
+            notes: changes.notes.to_owned(),
+            ptype: changes.ptype,
+            anomaly: changes.anomaly,
+            deleted: self.deleted,
+            created_at: self.created_at,
             modified_at: Utc::now(),
+            user_id: self.user_id,
+            station_id: changes.station_id,
         }
     }
 
-    pub fn create(conn: &PgConnection, entry: &Self) -> Result<Self, Box<dyn Error>> {
-        diesel::insert_into(precipitation_logs::table)
-            .values(entry)
-            .execute(conn)?;
+    /// Entity tag for this version of the entry. It changes whenever the entry is modified.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", micros(&self.modified_at))
+    }
 
-        Ok(precipitation_logs::table
-            .filter(precipitation_logs::id.eq(entry.id))
-            .first(conn)?
-        )
+    /// Stores a new entry. This and the other writes below record an `EntryRevision` for the
+    /// change, attributed to `changed_by`.
+    pub fn create(conn: &PgConnection, entry: &Self, changed_by: Uuid) -> Result<Self, AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            let created = diesel::insert_into(precipitation_logs::table)
+                .values(entry)
+                .get_result::<PrecipitationLog>(conn)?;
+
+            EntryRevision::record(conn, RevisionAction::Create, changed_by, None, Some(&created))?;
+            Ok(created)
+        })
     }
 
-    pub fn read_all(conn: &PgConnection) -> Result<Vec<Self>, Box<dyn Error>> {
-        Ok(precipitation_logs::table
+    /// Creates an entry again with the id it had before, as a revert does. Entries that were
+    /// purged can't be brought back.
+    pub fn recreate(conn: &PgConnection, entry: &Self, changed_by: Uuid) -> Result<Self, AppError> {
+        if EntryRevision::was_purged(conn, entry.user_id, entry.id)? {
+            return Err(AppError::Conflict("The entry was purged and can't be brought back".to_string()));
+        }
+        PrecipitationLog::create(conn, entry, changed_by)
+    }
+
+    pub fn read_all(conn: &PgConnection, user_id: Uuid, station_id: Option<Uuid>) -> Result<Vec<Self>, AppError> {
+        let mut query = precipitation_logs::table
+            .filter(precipitation_logs::user_id.eq(user_id))
             .filter(precipitation_logs::deleted.eq(false))
-            .load::<PrecipitationLog>(conn)?
-        )
+            .into_boxed();
+
+        if let Some(station_id) = station_id {
+            query = query.filter(precipitation_logs::station_id.eq(station_id));
+        }
+
+        Ok(query.load::<PrecipitationLog>(conn)?)
     }
 
-    pub fn read(conn: &PgConnection, id: Uuid) -> Result<Option<Self>, Box<dyn Error>> {
+    /// Returns at most `limit` entries matching `filter`, starting after `after` when it is given,
+    /// along with the cursor for the following page if there is one.
+    pub fn search(conn: &PgConnection, user_id: Uuid, filter: &EntryFilter, order: SortOrder, after: Option<&EntryCursor>, limit: i64) -> Result<(Vec<Self>, Option<EntryCursor>), AppError> {
+        let mut query = precipitation_logs::table
+            .filter(precipitation_logs::user_id.eq(user_id))
+            .filter(precipitation_logs::deleted.eq(false))
+            .into_boxed();
+
+        if let Some(station_id) = filter.station_id {
+            query = query.filter(precipitation_logs::station_id.eq(station_id));
+        }
+        if let Some(from) = filter.from {
+            query = query.filter(precipitation_logs::logged_at.ge(from));
+        }
+        if let Some(to) = filter.to {
+            query = query.filter(precipitation_logs::logged_at.lt(to));
+        }
+        if let Some(ptype) = filter.ptype {
+            query = query.filter(precipitation_logs::ptype.eq(ptype));
+        }
+        if let Some(anomaly) = filter.anomaly {
+            query = query.filter(precipitation_logs::anomaly.eq(anomaly));
+        }
+        if let Some(min) = filter.min_measurement {
+            query = query.filter(precipitation_logs::measurement.ge(min));
+        }
+        if let Some(max) = filter.max_measurement {
+            query = query.filter(precipitation_logs::measurement.le(max));
+        }
+
+        query = match (order, after) {
+            (SortOrder::Ascending, Some(cursor)) => query.filter(
+                precipitation_logs::logged_at.gt(cursor.logged_at).or(
+                    precipitation_logs::logged_at.eq(cursor.logged_at).and(precipitation_logs::id.gt(cursor.id))
+                )
+            ),
+            (SortOrder::Descending, Some(cursor)) => query.filter(
+                precipitation_logs::logged_at.lt(cursor.logged_at).or(
+                    precipitation_logs::logged_at.eq(cursor.logged_at).and(precipitation_logs::id.lt(cursor.id))
+                )
+            ),
+            (_, None) => query,
+        };
+
+        query = match order {
+            SortOrder::Ascending => query.order((precipitation_logs::logged_at.asc(), precipitation_logs::id.asc())),
+            SortOrder::Descending => query.order((precipitation_logs::logged_at.desc(), precipitation_logs::id.desc())),
+        };
+
+        // Fetch one extra row to find out whether another page follows this one.
+        let mut entries = query.limit(limit + 1).load::<PrecipitationLog>(conn)?;
+        let next = if entries.len() as i64 > limit {
+            entries.truncate(limit as usize);
+            entries.last().map(EntryCursor::for_entry)
+        } else {
+            None
+        };
+
+        Ok((entries, next))
+    }
+
+    pub fn read(conn: &PgConnection, user_id: Uuid, id: Uuid) -> Result<Option<Self>, AppError> {
         let result: Vec<PrecipitationLog> = precipitation_logs::table
             .filter(precipitation_logs::id.eq(id))
+            .filter(precipitation_logs::user_id.eq(user_id))
+            .filter(precipitation_logs::deleted.eq(false))
             .load::<PrecipitationLog>(conn)?;
 
         if result.len() == 1 {
@@ -105,54 +305,127 @@ impl PrecipitationLog {
         }
     }
 
-    pub fn upsert(conn: &PgConnection, user_entry: &Self) -> Result<Self, Box<dyn Error>> {
-        match PrecipitationLog::read(conn, user_entry.id)? {
-            Some(db_entry) => {
-                let entry = PrecipitationLog::new_for_upsert(&db_entry, user_entry);
-                PrecipitationLog::update(conn, &entry)
-            }
-            None => {
-                let entry = PrecipitationLog::new(
-                    user_entry.measurement,
-                    user_entry.logged_at,
-                    PrecipitationType::from_i16(user_entry.ptype),
-                    user_entry.notes.to_owned(),
-                    user_entry.anomaly,
-                );
-                PrecipitationLog::create(conn, &entry)
+    /// The user's deleted entries, most recently deleted first. `modified_at` is when they were
+    /// deleted.
+    pub fn read_trash(conn: &PgConnection, user_id: Uuid) -> Result<Vec<Self>, AppError> {
+        Ok(precipitation_logs::table
+            .filter(precipitation_logs::user_id.eq(user_id))
+            .filter(precipitation_logs::deleted.eq(true))
+            .order(precipitation_logs::modified_at.desc())
+            .load::<PrecipitationLog>(conn)?
+        )
+    }
+
+    pub fn is_in_trash(conn: &PgConnection, user_id: Uuid, id: Uuid) -> Result<bool, AppError> {
+        Ok(diesel::select(diesel::dsl::exists(precipitation_logs::table
+            .filter(precipitation_logs::id.eq(id))
+            .filter(precipitation_logs::user_id.eq(user_id))
+            .filter(precipitation_logs::deleted.eq(true))
+        )).get_result(conn)?)
+    }
+
+    pub fn update(conn: &PgConnection, entry: &Self, changed_by: Uuid) -> Result<Self, AppError> {
+        PrecipitationLog::write_update(conn, entry, None, changed_by)
+    }
+
+    /// Like `update`, but only if the stored entry still has the `modified_at` it was read with.
+    /// Fails with `PreconditionFailed` if someone changed it in the meantime.
+    pub fn update_if_unmodified(conn: &PgConnection, entry: &Self, modified_at: DateTime<Utc>, changed_by: Uuid) -> Result<Self, AppError> {
+        PrecipitationLog::write_update(conn, entry, Some(modified_at), changed_by)
+    }
+
+    fn write_update(conn: &PgConnection, entry: &Self, modified_at: Option<DateTime<Utc>>, changed_by: Uuid) -> Result<Self, AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            let before = PrecipitationLog::lock_owned(conn, entry.user_id, entry.id, false)?;
+            if modified_at.map_or(false, |m| m != before.modified_at) {
+                return Err(AppError::PreconditionFailed);
             }
-        }
+
+            let after = diesel::update(precipitation_logs::table.find(entry.id))
+                .set(entry)
+                .get_result::<PrecipitationLog>(conn)?;
+
+            EntryRevision::record(conn, RevisionAction::Update, changed_by, Some(&before), Some(&after))?;
+            Ok(after)
+        })
     }
 
-    pub fn update(conn: &PgConnection, entry: &Self) -> Result<Self, Box<dyn Error>> {
-        diesel::update(precipitation_logs::table)
-            .set(entry)
-            .filter(precipitation_logs::id.eq(entry.id))
-            .execute(conn)?;
+    /// Moves an entry to the trash. It's hidden from every read except `read_trash` until it's
+    /// restored or purged.
+    pub fn soft_delete(conn: &PgConnection, user_id: Uuid, id: Uuid, changed_by: Uuid) -> Result<(), AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            let before = PrecipitationLog::lock_owned(conn, user_id, id, false)?;
 
-        let result = PrecipitationLog::read(conn, entry.id)?;
+            diesel::update(precipitation_logs::table.find(id))
+                .set((precipitation_logs::deleted.eq(true), precipitation_logs::modified_at.eq(Utc::now())))
+                .execute(conn)?;
 
-        match result {
-            Some(e) => Ok(e),
-            None => Err(Box::new(diesel::NotFound))
-        }
+            EntryRevision::record(conn, RevisionAction::Delete, changed_by, Some(&before), None)?;
+            Ok(())
+        })
     }
 
-    pub fn soft_delete(conn: &PgConnection, id: Uuid) -> Result<(), Box<dyn Error>> {
-        diesel::update(precipitation_logs::table)
-            .set(precipitation_logs::deleted.eq(true))
-            .filter(precipitation_logs::id.eq(id))
+    pub fn restore(conn: &PgConnection, user_id: Uuid, id: Uuid, changed_by: Uuid) -> Result<Self, AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            PrecipitationLog::lock_owned(conn, user_id, id, true)?;
+
+            let after = diesel::update(precipitation_logs::table.find(id))
+                .set((precipitation_logs::deleted.eq(false), precipitation_logs::modified_at.eq(Utc::now())))
+                .get_result::<PrecipitationLog>(conn)?;
+
+            EntryRevision::record(conn, RevisionAction::Restore, changed_by, None, Some(&after))?;
+            Ok(after)
+        })
+    }
+
+    /// Removes an entry in the trash for good, whoever owns it. Only its history is left.
+    pub fn purge(conn: &PgConnection, id: Uuid, changed_by: Uuid) -> Result<(), AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            let before = PrecipitationLog::lock(conn, id, true)?.ok_or(AppError::NotFound)?;
+            PrecipitationLog::remove(conn, &before, changed_by)
+        })
+    }
+
+    /// Purges every entry that went into the trash before `deleted_before`, returning how many.
+    pub fn purge_deleted_before(conn: &PgConnection, deleted_before: DateTime<Utc>, changed_by: Uuid) -> Result<usize, AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            let entries = precipitation_logs::table
+                .filter(precipitation_logs::deleted.eq(true))
+                .filter(precipitation_logs::modified_at.lt(deleted_before))
+                .for_update()
+                .load::<PrecipitationLog>(conn)?;
+
+            for entry in entries.iter() {
+                PrecipitationLog::remove(conn, entry, changed_by)?;
+            }
+            Ok(entries.len())
+        })
+    }
+
+    fn remove(conn: &PgConnection, entry: &Self, changed_by: Uuid) -> Result<(), AppError> {
+        diesel::delete(precipitation_logs::table.find(entry.id))
             .execute(conn)?;
 
+        EntryRevision::record(conn, RevisionAction::Purge, changed_by, Some(entry), None)?;
         Ok(())
     }
 
-    pub fn delete(conn: &PgConnection, id: Uuid) -> Result<(), Box<dyn Error>> {
-        diesel::delete(precipitation_logs::table)
+    // Reads the entry, in or out of the trash, and locks its row until the transaction ends so
+    // the revision's "before" is what actually got changed.
+    fn lock(conn: &PgConnection, id: Uuid, deleted: bool) -> Result<Option<Self>, AppError> {
+        Ok(precipitation_logs::table
             .filter(precipitation_logs::id.eq(id))
-            .execute(conn)?;
+            .filter(precipitation_logs::deleted.eq(deleted))
+            .for_update()
+            .first::<PrecipitationLog>(conn)
+            .optional()?
+        )
+    }
 
-        Ok(())
+    fn lock_owned(conn: &PgConnection, user_id: Uuid, id: Uuid, deleted: bool) -> Result<Self, AppError> {
+        PrecipitationLog::lock(conn, id, deleted)?
+            .filter(|e| e.user_id == user_id)
+            .ok_or(AppError::NotFound)
     }
 }
 
@@ -177,8 +450,46 @@ mod tests {
      * These really are integration tests, not unit tests. I've marked them all
      * ignored as they shouldn't be called during the normal test runs, and only
      * are useful for working on the database.
+     *
+     * Run crate::models::user::User::tests::test_create_user() before running these tests!
      */
 
+    fn test_user_id() -> Uuid {
+        Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap()
+    }
+
+    // YOU MUST UPDATE ID WITH A REAL STATION OWNED BY THE TEST USER BEFORE TESTING!
+    fn test_station_id() -> Uuid {
+        Uuid::parse_str("0b7f3c52-91a4-4c1e-8d0e-6a5f2b9e4c17").unwrap()
+    }
+
+    #[test]
+    fn entry_cursor_round_trip() {
+        let cursor = EntryCursor {
+            logged_at: Utc.timestamp(1593827229, 123_456_000),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(Some(cursor.clone()), EntryCursor::decode(&cursor.encode()));
+    }
+
+    #[test]
+    fn entry_cursor_rejects_garbage() {
+        assert_eq!(None, EntryCursor::decode("not-a-cursor"));
+        assert_eq!(None, EntryCursor::decode("12345_not-a-uuid"));
+    }
+
+    #[test]
+    fn precipitation_type_round_trip() {
+        for ptype in &[PrecipitationType::Unidentified, PrecipitationType::Liquid, PrecipitationType::Freezing, PrecipitationType::Frozen] {
+            assert_eq!(Some(*ptype), PrecipitationType::from_i16(*ptype as i16));
+            assert_eq!(Some(*ptype), PrecipitationType::parse(ptype.as_str()));
+            assert_eq!(format!("\"{}\"", ptype.as_str()), serde_json::to_string(ptype).unwrap());
+        }
+        assert_eq!(None, PrecipitationType::from_i16(4));
+        assert!(serde_json::from_str::<PrecipitationType>("\"hail\"").is_err());
+        assert!(serde_json::from_str::<PrecipitationType>("1").is_err());
+    }
+
     #[test]
     #[ignore]
     fn create_precipitation_log_entry() {
@@ -186,18 +497,42 @@ mod tests {
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
         let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
         let entry = PrecipitationLog::new(
+            test_user_id(),
+            test_station_id(),
             5.0,
             Utc::now(),
             PrecipitationType::Liquid,
             Some("This is a note".to_string()),
             true,
         );
-        let result = PrecipitationLog::create(&connection, &entry).expect("Failed to create entry");
+        let result = PrecipitationLog::create(&connection, &entry, test_user_id()).expect("Failed to create entry");
         assert_eq!(entry.measurement, result.measurement);
         assert_eq!(entry.logged_at.timestamp(), result.logged_at.timestamp());
         assert_eq!(entry.ptype, result.ptype);
         assert_eq!(entry.notes.unwrap_or_default(), result.notes.unwrap_or_default());
         assert_eq!(entry.anomaly, result.anomaly);
+        assert_eq!(entry.user_id, result.user_id);
+        assert_eq!(entry.station_id, result.station_id);
+    }
+
+    #[test]
+    #[ignore]
+    fn read_precipitation_log_entry_as_other_user() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let entry = PrecipitationLog::new(
+            test_user_id(),
+            test_station_id(),
+            2.5,
+            Utc::now(),
+            PrecipitationType::Liquid,
+            None,
+            false,
+        );
+        PrecipitationLog::create(&connection, &entry, test_user_id()).expect("Failed to create entry");
+        let result = PrecipitationLog::read(&connection, Uuid::new_v4(), entry.id).expect("Failed to read entry.");
+        assert!(result.is_none());
     }
 
     #[test]
@@ -207,16 +542,33 @@ mod tests {
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
         let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
         let id = Uuid::parse_str("f76f799b-c7be-4553-a48d-8c282df7cc9c").expect("Failed to parse ID");
-        let mut entry = PrecipitationLog::read(&connection, id).expect("Failed to fetch entry.").unwrap();
+        let mut entry = PrecipitationLog::read(&connection, test_user_id(), id).expect("Failed to fetch entry.").unwrap();
         let expected_measurement = 10.0;
         let expected_notes = Some("I have changed the notes.".to_string());
         entry.measurement = expected_measurement;
         entry.notes = expected_notes.clone();
-        let result = PrecipitationLog::update(&connection, &entry).expect("Failed to update entry.");
+        let result = PrecipitationLog::update(&connection, &entry, test_user_id()).expect("Failed to update entry.");
         assert_eq!(expected_measurement, result.measurement);
         assert_eq!(expected_notes.unwrap(), entry.notes.expect("Result notes unset."));
     }
 
+    #[test]
+    #[ignore]
+    fn update_stale_precipitation_log_entry() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let entry = PrecipitationLog::new(test_user_id(), test_station_id(), 1.0, Utc::now(), PrecipitationType::Liquid, None, false);
+        let entry = PrecipitationLog::create(&connection, &entry, test_user_id()).expect("Failed to create entry");
+        let mut changed = entry.clone();
+        changed.measurement = 2.0;
+        changed.modified_at = Utc::now();
+        let first = PrecipitationLog::update_if_unmodified(&connection, &changed, entry.modified_at, test_user_id()).expect("Failed to update entry.");
+        assert_ne!(entry.etag(), first.etag());
+        let second = PrecipitationLog::update_if_unmodified(&connection, &changed, entry.modified_at, test_user_id());
+        assert!(matches!(second, Err(AppError::PreconditionFailed)));
+    }
+
     #[test]
     #[ignore]
     fn soft_delete_precipitation_log_entry() {
@@ -224,21 +576,53 @@ mod tests {
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
         let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
         let id = Uuid::parse_str("5994b445-a8d8-4918-a1ca-00d09299688f").expect("Failed to parse ID");
-        let before_result = PrecipitationLog::read_all(&connection).expect("Failed to read all.");
-        PrecipitationLog::soft_delete(&connection, id).expect("Failed to delete entry.");
-        let after_result = PrecipitationLog::read_all(&connection).expect("Failed to read all.");
+        let before_result = PrecipitationLog::read_all(&connection, test_user_id(), None).expect("Failed to read all.");
+        PrecipitationLog::soft_delete(&connection, test_user_id(), id, test_user_id()).expect("Failed to delete entry.");
+        let after_result = PrecipitationLog::read_all(&connection, test_user_id(), None).expect("Failed to read all.");
         assert_eq!(before_result.len() - 1, after_result.len());
     }
 
     #[test]
     #[ignore]
-    fn delete_precipitation_log_entry() {
+    fn restore_precipitation_log_entry() {
         initialize();
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
         let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
-        let id = Uuid::parse_str("f76f799b-c7be-4553-a48d-8c282df7cc9c").expect("Failed to parse ID");
-        PrecipitationLog::delete(&connection, id).expect("Failed to delete entry.");
-        let result = PrecipitationLog::read_all(&connection).expect("Failed to read all.");
-        assert_eq!(0, result.len());
+        let entry = PrecipitationLog::new(test_user_id(), test_station_id(), 3.0, Utc::now(), PrecipitationType::Frozen, None, false);
+        PrecipitationLog::create(&connection, &entry, test_user_id()).expect("Failed to create entry");
+        PrecipitationLog::soft_delete(&connection, test_user_id(), entry.id, test_user_id()).expect("Failed to delete entry.");
+        assert!(PrecipitationLog::read(&connection, test_user_id(), entry.id).expect("Failed to read entry.").is_none());
+        let trash = PrecipitationLog::read_trash(&connection, test_user_id()).expect("Failed to read trash.");
+        assert!(trash.iter().any(|e| e.id == entry.id));
+        PrecipitationLog::restore(&connection, test_user_id(), entry.id, test_user_id()).expect("Failed to restore entry.");
+        assert!(PrecipitationLog::read(&connection, test_user_id(), entry.id).expect("Failed to read entry.").is_some());
+    }
+
+    #[test]
+    #[ignore]
+    fn purge_precipitation_log_entry() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let entry = PrecipitationLog::new(test_user_id(), test_station_id(), 3.0, Utc::now(), PrecipitationType::Frozen, None, false);
+        PrecipitationLog::create(&connection, &entry, test_user_id()).expect("Failed to create entry");
+        assert!(matches!(PrecipitationLog::purge(&connection, entry.id, test_user_id()), Err(AppError::NotFound)));
+        PrecipitationLog::soft_delete(&connection, test_user_id(), entry.id, test_user_id()).expect("Failed to delete entry.");
+        PrecipitationLog::purge(&connection, entry.id, test_user_id()).expect("Failed to purge entry.");
+        assert!(!PrecipitationLog::is_in_trash(&connection, test_user_id(), entry.id).expect("Failed to read trash."));
+    }
+
+    #[test]
+    #[ignore]
+    fn purged_precipitation_log_entry_stays_gone() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let entry = PrecipitationLog::new(test_user_id(), test_station_id(), 3.0, Utc::now(), PrecipitationType::Frozen, None, false);
+        let created = PrecipitationLog::create(&connection, &entry, test_user_id()).expect("Failed to create entry");
+        PrecipitationLog::soft_delete(&connection, test_user_id(), entry.id, test_user_id()).expect("Failed to delete entry.");
+        PrecipitationLog::purge(&connection, entry.id, test_user_id()).expect("Failed to purge entry.");
+        assert!(matches!(PrecipitationLog::recreate(&connection, &created, test_user_id()), Err(AppError::Conflict(_))));
+        assert!(PrecipitationLog::read(&connection, test_user_id(), entry.id).expect("Failed to read entry.").is_none());
     }
 }
@@ -0,0 +1,112 @@
+use chrono::prelude::*;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use rand::RngCore;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::user::User;
+use crate::schema::recovery_codes;
+use crate::utils::token::hash_secret;
+
+/// How many recovery codes a user gets each time they're generated.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// A one-time code that stands in for a TOTP code when the authenticator is lost. Only a hash
+/// of the code is stored.
+#[derive(Identifiable, Associations, Queryable, Insertable, Clone)]
+#[belongs_to(User)]
+#[table_name = "recovery_codes"]
+pub struct RecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code_hash: String,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Codes look like `1a2b3-c4d5e`. The dash and case are ignored when they're redeemed.
+fn generate_code() -> String {
+    let mut bytes = [0u8; 5];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl RecoveryCode {
+    /// Replaces any codes the user has with a fresh set, returning the codes to show them. They
+    /// can't be recovered later.
+    pub fn replace_for_user(conn: &PgConnection, user_id: Uuid) -> Result<Vec<String>, AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            RecoveryCode::delete_for_user(conn, user_id)?;
+
+            let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_code()).collect();
+            let rows: Vec<RecoveryCode> = codes.iter()
+                .map(|code| RecoveryCode {
+                    id: Uuid::new_v4(),
+                    user_id,
+                    code_hash: hash_secret(normalize(code).as_str()),
+                    used_at: None,
+                    created_at: Utc::now(),
+                })
+                .collect();
+
+            diesel::insert_into(recovery_codes::table)
+                .values(&rows)
+                .execute(conn)?;
+
+            Ok(codes)
+        })
+    }
+
+    /// Uses up one of the user's codes. Returns false if it doesn't match an unused code.
+    pub fn redeem(conn: &PgConnection, user_id: Uuid, code: &str) -> Result<bool, AppError> {
+        let count = diesel::update(recovery_codes::table)
+            .set(recovery_codes::used_at.eq(Utc::now()))
+            .filter(recovery_codes::user_id.eq(user_id))
+            .filter(recovery_codes::code_hash.eq(hash_secret(normalize(code).as_str())))
+            .filter(recovery_codes::used_at.is_null())
+            .execute(conn)?;
+
+        Ok(count > 0)
+    }
+
+    pub fn remaining(conn: &PgConnection, user_id: Uuid) -> Result<i64, AppError> {
+        Ok(recovery_codes::table
+            .filter(recovery_codes::user_id.eq(user_id))
+            .filter(recovery_codes::used_at.is_null())
+            .count()
+            .get_result(conn)?
+        )
+    }
+
+    pub fn delete_for_user(conn: &PgConnection, user_id: Uuid) -> Result<(), AppError> {
+        diesel::delete(recovery_codes::table)
+            .filter(recovery_codes::user_id.eq(user_id))
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_redeem_regardless_of_formatting() {
+        let code = generate_code();
+        assert_eq!(11, code.len());
+        assert_eq!(normalize(code.as_str()), normalize(code.to_uppercase().replace("-", " ").as_str()));
+        assert_eq!("1a2b3c4d5e", normalize(" 1A2B3-c4d5e "));
+    }
+}
@@ -0,0 +1,108 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::api_token::ApiToken;
+use crate::schema::refresh_tokens;
+use crate::utils::token::{generate_secret, hash_secret};
+
+/// A single-use token that trades in for a new access JWT. Only a hash of the secret is stored.
+#[derive(Identifiable, Associations, Queryable, Insertable, Clone)]
+#[belongs_to(ApiToken)]
+#[table_name = "refresh_tokens"]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub api_token_id: Uuid,
+    pub token_hash: String,
+    pub used: bool,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RefreshToken {
+    /// Creates the next refresh token for a session, returning it with the secret to give the
+    /// client. The secret can't be recovered later.
+    pub fn issue(conn: &PgConnection, api_token: &ApiToken, lifetime: Duration) -> Result<(Self, String), AppError> {
+        let secret = generate_secret();
+        let token = RefreshToken {
+            id: Uuid::new_v4(),
+            api_token_id: api_token.id,
+            token_hash: hash_secret(secret.as_str()),
+            used: false,
+            expires_at: Utc::now() + lifetime,
+            created_at: Utc::now(),
+        };
+
+        diesel::insert_into(refresh_tokens::table)
+            .values(&token)
+            .execute(conn)?;
+
+        Ok((token, secret))
+    }
+
+    pub fn find_by_secret(conn: &PgConnection, secret: &str) -> Result<Option<Self>, AppError> {
+        Ok(refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(hash_secret(secret)))
+            .first::<RefreshToken>(conn)
+            .optional()?
+        )
+    }
+
+    /// Marks the token used. Returns false if it already was, which means the token has been
+    /// presented twice.
+    pub fn mark_used(conn: &PgConnection, id: Uuid) -> Result<bool, AppError> {
+        let count = diesel::update(refresh_tokens::table)
+            .set(refresh_tokens::used.eq(true))
+            .filter(refresh_tokens::id.eq(id))
+            .filter(refresh_tokens::used.eq(false))
+            .execute(conn)?;
+
+        Ok(count == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    /*
+     * These really are integration tests, not unit tests. I've marked them all
+     * ignored as they shouldn't be called during the normal test runs, and only
+     * are useful for working on the database.
+     */
+
+    #[test]
+    #[ignore]
+    fn refresh_token_is_single_use() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+
+        // YOU MUST UPDATE ID WITH A REAL ID BEFORE TESTING!
+        let api_token_id = Uuid::parse_str("1de3f9a9-4e61-4415-80a1-2f65275f0a3e").unwrap();
+        let api_token = ApiToken::get(&connection, api_token_id).unwrap();
+
+        let (token, secret) = RefreshToken::issue(&connection, &api_token, Duration::days(1)).unwrap();
+        let found = RefreshToken::find_by_secret(&connection, &secret).unwrap().expect("Token not found.");
+        assert_eq!(token.id, found.id);
+        assert!(RefreshToken::mark_used(&connection, token.id).unwrap());
+        assert!(!RefreshToken::mark_used(&connection, token.id).unwrap());
+    }
+}
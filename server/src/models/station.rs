@@ -0,0 +1,162 @@
+use chrono::prelude::*;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::user::User;
+use crate::schema::stations;
+
+#[derive(Serialize, Deserialize, Identifiable, Associations, AsChangeset, Queryable, Insertable, Clone)]
+#[belongs_to(User)]
+#[table_name = "stations"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct Station {
+    pub id: Uuid,
+    #[serde(skip_deserializing)]
+    pub user_id: Uuid,
+    pub name: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub elevation: Option<f32>,
+    pub time_zone: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+}
+
+impl Station {
+    pub fn new(user_id: Uuid, name: String, latitude: Option<f64>, longitude: Option<f64>, elevation: Option<f32>, time_zone: String, active: bool) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            latitude,
+            longitude,
+            elevation,
+            time_zone,
+            active,
+            created_at: Utc::now(),
+            modified_at: Utc::now(),
+        }
+    }
+
+    pub fn create(conn: &PgConnection, station: &Self) -> Result<Self, AppError> {
+        diesel::insert_into(stations::table)
+            .values(station)
+            .execute(conn)?;
+
+        Ok(stations::table
+            .filter(stations::id.eq(station.id))
+            .first(conn)?
+        )
+    }
+
+    pub fn read_all(conn: &PgConnection, user_id: Uuid) -> Result<Vec<Self>, AppError> {
+        Ok(stations::table
+            .filter(stations::user_id.eq(user_id))
+            .order(stations::name.asc())
+            .load::<Station>(conn)?
+        )
+    }
+
+    pub fn read(conn: &PgConnection, user_id: Uuid, id: Uuid) -> Result<Option<Self>, AppError> {
+        Ok(stations::table
+            .filter(stations::id.eq(id))
+            .filter(stations::user_id.eq(user_id))
+            .first::<Station>(conn)
+            .optional()?
+        )
+    }
+
+    pub fn update(conn: &PgConnection, station: &Self) -> Result<Self, AppError> {
+        let count = diesel::update(stations::table)
+            .set(station)
+            .filter(stations::id.eq(station.id))
+            .filter(stations::user_id.eq(station.user_id))
+            .execute(conn)?;
+
+        if count == 0 {
+            return Err(AppError::NotFound);
+        }
+
+        Ok(stations::table.find(station.id).first(conn)?)
+    }
+
+    pub fn delete(conn: &PgConnection, user_id: Uuid, id: Uuid) -> Result<(), AppError> {
+        let count = diesel::delete(stations::table)
+            .filter(stations::id.eq(id))
+            .filter(stations::user_id.eq(user_id))
+            .execute(conn)?;
+
+        match count {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    /*
+     * These really are integration tests, not unit tests. I've marked them all
+     * ignored as they shouldn't be called during the normal test runs, and only
+     * are useful for working on the database.
+     *
+     * Run crate::models::user::User::tests::test_create_user() before running these tests!
+     */
+
+    fn test_user_id() -> Uuid {
+        Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn create_station() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let station = Station::new(
+            test_user_id(),
+            "Back yard".to_string(),
+            Some(39.7392),
+            Some(-104.9903),
+            Some(1609.0),
+            "America/Denver".to_string(),
+            true,
+        );
+        let result = Station::create(&connection, &station).expect("Failed to create station.");
+        assert_eq!(station.name, result.name);
+        assert_eq!(station.latitude, result.latitude);
+        assert_eq!(station.time_zone, result.time_zone);
+    }
+
+    #[test]
+    #[ignore]
+    fn update_station() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let station = Station::new(test_user_id(), "Roof".to_string(), None, None, None, "UTC".to_string(), true);
+        let mut station = Station::create(&connection, &station).expect("Failed to create station.");
+        station.active = false;
+        let result = Station::update(&connection, &station).expect("Failed to update station.");
+        assert_eq!(false, result.active);
+    }
+}
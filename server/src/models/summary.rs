@@ -0,0 +1,204 @@
+use chrono::prelude::*;
+use diesel;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Float4, Nullable, SmallInt, Text, Timestamp, Timestamptz};
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::precipitation_log::PrecipitationType;
+
+/// Size of the buckets entries are summed into. Weeks start on Monday, as Postgres' `date_trunc`
+/// does.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "day" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            "year" => Some(Period::Year),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Year => "year",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Totals {
+    pub total: f32,
+    pub max: f32,
+    pub entries: i64,
+    pub wet_days: i64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TypeTotals {
+    pub ptype: PrecipitationType,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PrecipitationSummary {
+    pub start: NaiveDate,
+    #[serde(flatten)]
+    pub totals: Totals,
+    pub by_type: Vec<TypeTotals>,
+}
+
+#[derive(QueryableByName)]
+struct SummaryRow {
+    #[sql_type = "Timestamp"]
+    bucket: NaiveDateTime,
+    #[sql_type = "Nullable<SmallInt>"]
+    ptype: Option<PrecipitationType>,
+    #[sql_type = "Float4"]
+    total: f32,
+    #[sql_type = "Float4"]
+    max_measurement: f32,
+    #[sql_type = "BigInt"]
+    entry_count: i64,
+    #[sql_type = "BigInt"]
+    wet_days: i64,
+}
+
+impl SummaryRow {
+    fn totals(&self) -> Totals {
+        Totals {
+            total: self.total,
+            max: self.max_measurement,
+            entries: self.entry_count,
+            wet_days: self.wet_days,
+        }
+    }
+}
+
+// The grouping sets produce one row per (bucket, ptype) plus a row per bucket with a NULL ptype
+// holding the bucket-wide totals, which sorts first.
+const SUMMARY_QUERY: &str = "
+    SELECT bucket,
+           ptype,
+           sum(measurement)                                    AS total,
+           max(measurement)                                    AS max_measurement,
+           count(*)                                            AS entry_count,
+           count(DISTINCT day) FILTER (WHERE measurement > 0)  AS wet_days
+    FROM (SELECT date_trunc($1, logged_at AT TIME ZONE $2)    AS bucket,
+                 date_trunc('day', logged_at AT TIME ZONE $2) AS day,
+                 ptype,
+                 measurement
+          FROM precipitation_logs
+          WHERE user_id = $3
+            AND deleted = false
+            AND ($4::uuid IS NULL OR station_id = $4)
+            AND ($5::timestamptz IS NULL OR logged_at >= $5)
+            AND ($6::timestamptz IS NULL OR logged_at < $6)) AS entries
+    GROUP BY GROUPING SETS ((bucket, ptype), (bucket))
+    ORDER BY bucket, ptype NULLS FIRST";
+
+impl PrecipitationSummary {
+    /// Sums a user's entries into `period` sized buckets, with bucket boundaries and wet days
+    /// computed in the `time_zone` local time.
+    pub fn read(
+        conn: &PgConnection,
+        user_id: Uuid,
+        period: Period,
+        time_zone: &str,
+        station_id: Option<Uuid>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Self>, AppError> {
+        let rows: Vec<SummaryRow> = diesel::sql_query(SUMMARY_QUERY)
+            .bind::<Text, _>(period.as_str())
+            .bind::<Text, _>(time_zone)
+            .bind::<diesel::sql_types::Uuid, _>(user_id)
+            .bind::<Nullable<diesel::sql_types::Uuid>, _>(station_id)
+            .bind::<Nullable<Timestamptz>, _>(from)
+            .bind::<Nullable<Timestamptz>, _>(to)
+            .load(conn)?;
+
+        let mut summaries: Vec<Self> = Vec::new();
+        for row in rows {
+            match row.ptype {
+                None => summaries.push(PrecipitationSummary {
+                    start: row.bucket.date(),
+                    totals: row.totals(),
+                    by_type: Vec::new(),
+                }),
+                Some(ptype) => {
+                    if let Some(summary) = summaries.last_mut() {
+                        summary.by_type.push(TypeTotals {
+                            ptype,
+                            totals: row.totals(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Once;
+
+    use dotenv::dotenv;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+
+    fn initialize() {
+        INIT.call_once(|| {
+            dotenv().ok();
+        });
+    }
+
+    #[test]
+    fn period_round_trip() {
+        for period in &[Period::Day, Period::Week, Period::Month, Period::Year] {
+            assert_eq!(Some(*period), Period::parse(period.as_str()));
+        }
+        assert_eq!(None, Period::parse("fortnight"));
+    }
+
+    /*
+     * This really is an integration test, not a unit test. It's marked ignored as it shouldn't be
+     * called during the normal test runs, and is only useful for working on the database.
+     *
+     * Run crate::models::user::User::tests::test_create_user() before running this test!
+     */
+
+    #[test]
+    #[ignore]
+    fn read_monthly_summary() {
+        initialize();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+        let connection = PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url));
+        let user_id = Uuid::parse_str("88280065-d1da-4255-8e29-0a09da2da88a").unwrap();
+        let result = PrecipitationSummary::read(&connection, user_id, Period::Month, "UTC", None, None, None)
+            .expect("Failed to read summary.");
+        for summary in result {
+            let entries: i64 = summary.by_type.iter().map(|t| t.totals.entries).sum();
+            assert_eq!(summary.totals.entries, entries);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::error::Error;
+use std::fmt;
 
-use bcrypt::verify;
+use bcrypt::{BcryptError, DEFAULT_COST, hash, verify};
 use chrono::prelude::*;
 use diesel;
 use diesel::pg::PgConnection;
@@ -8,7 +9,50 @@ use diesel::prelude::*;
 use log::debug;
 use uuid::Uuid;
 
-use crate::schema::users;
+use crate::error::AppError;
+use crate::models::recovery_code::RecoveryCode;
+use crate::schema::{api_tokens, users};
+use crate::utils::totp;
+
+// A hash of a throwaway password at `DEFAULT_COST`, checked when a login names no known user so
+// that it costs as much as a real check.
+const UNKNOWN_USER_HASH: &str = "$2b$12$oJr8Cv.6YS0gn/gbuS7AvuNgks2qQO4R4wcLXNZQGCc.5d853OeBu";
+
+/// What a user is allowed to do. Admins manage accounts, observers record readings and viewers
+/// can only read them.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Observer,
+    Viewer,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "admin" => Some(Role::Admin),
+            "observer" => Some(Role::Observer),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Observer => "observer",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn can_write(&self) -> bool {
+        match self {
+            Role::Admin | Role::Observer => true,
+            Role::Viewer => false,
+        }
+    }
+}
 
 #[derive(Identifiable, AsChangeset, Queryable, Insertable, Clone)]
 #[table_name = "users"]
@@ -19,10 +63,36 @@ pub struct User {
     pub enabled: bool,
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
+    pub role: String,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: i64,
 }
 
 impl User {
-    pub fn create(conn: &PgConnection, user: Self) -> Result<Self, Box<dyn Error>> {
+    /// Builds a new user, hashing `password` with bcrypt.
+    pub fn new(name: String, password: &str, role: Role) -> Result<Self, AppError> {
+        Ok(User {
+            id: Uuid::new_v4(),
+            name,
+            password: hash(password, DEFAULT_COST)?,
+            enabled: true,
+            created_at: Utc::now(),
+            modified_at: Utc::now(),
+            role: role.as_str().to_string(),
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: 0,
+        })
+    }
+
+    // The column is constrained to known roles, but if that's ever bypassed fall back to the
+    // least privileged one.
+    pub fn role(&self) -> Role {
+        Role::parse(self.role.as_str()).unwrap_or(Role::Viewer)
+    }
+
+    pub fn create(conn: &PgConnection, user: Self) -> Result<Self, AppError> {
         diesel::insert_into(users::table)
             .values(&user)
             .execute(conn)?;
@@ -30,42 +100,172 @@ impl User {
         Ok(users::table.filter(users::id.eq(user.id)).first(conn)?)
     }
 
-    pub fn validate(conn: &PgConnection, username: String, password: String) -> Result<Self, Box<dyn Error>> {
-        let user: User = users::table
+    /// Checks a name and password. Disabled accounts are only reported as such once the password
+    /// has been verified, so they can't be discovered by guessing names. Unknown names still get
+    /// a password check, against `UNKNOWN_USER_HASH`, so they take as long to turn down.
+    pub fn validate(conn: &PgConnection, username: String, password: String) -> Result<Self, LoginError> {
+        let user: User = match users::table
             .filter(
                 users::name.eq(username)
-            ).first::<User>(conn)?;
+            ).first::<User>(conn) {
+            Ok(u) => u,
+            Err(diesel::NotFound) => {
+                let _ = verify(password, UNKNOWN_USER_HASH);
+                return Err(LoginError::InvalidCredentials);
+            }
+            Err(err) => return Err(LoginError::Database(err)),
+        };
+
         match verify(password, user.password.as_str()) {
-            Ok(_) => Ok(user),
+            Ok(true) if user.enabled => Ok(user),
+            Ok(true) => Err(LoginError::Disabled),
+            Ok(false) => Err(LoginError::InvalidCredentials),
             Err(err) => {
                 debug!("{}", err.to_string());
-                Err(err.into())
+                Err(LoginError::Hash(err))
             }
         }
     }
 
-    pub fn read_all(conn: &PgConnection) -> Result<Vec<Self>, Box<dyn Error>> {
+    pub fn read_all(conn: &PgConnection) -> Result<Vec<Self>, AppError> {
         Ok(users::table.load::<User>(conn)?)
     }
 
-    pub fn read(conn: &PgConnection, id: Uuid) -> Result<Vec<Self>, Box<dyn Error>> {
-        // todo!("Fix this so it properly returns only 1 user.")
-        Ok(users::table.filter(users::id.eq(id)).load(conn)?)
+    pub fn read(conn: &PgConnection, id: Uuid) -> Result<Self, AppError> {
+        Ok(users::table.find(id).first(conn)?)
+    }
+
+    pub fn update(conn: &PgConnection, id: Uuid, update: UserUpdateSet) -> Result<(), AppError> {
+        let count = diesel::update(users::table).set(&update).filter(users::id.eq(id)).execute(conn)?;
+
+        match count {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hashes and stores a new password. Checking it against the password policy is up to the
+    /// caller.
+    pub fn set_password(conn: &PgConnection, id: Uuid, password: &str) -> Result<(), AppError> {
+        let count = diesel::update(users::table)
+            .set((
+                users::password.eq(hash(password, DEFAULT_COST)?),
+                users::modified_at.eq(Utc::now()),
+            ))
+            .filter(users::id.eq(id))
+            .execute(conn)?;
+
+        match count {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        }
     }
 
-    pub fn update(conn: &PgConnection, id: Uuid, update: UserUpdateSet) -> Result<(), Box<dyn Error>> {
-        diesel::update(users::table).set(&update).filter(users::id.eq(id)).execute(conn)?;
+    /// Stores a new TOTP secret awaiting confirmation. Two-factor stays off until
+    /// `enable_totp` is called with a step the secret produced a code for.
+    pub fn start_totp_enrollment(conn: &PgConnection, id: Uuid, secret: &str) -> Result<(), AppError> {
+        diesel::update(users::table)
+            .set((
+                users::totp_secret.eq(Some(secret)),
+                users::totp_enabled.eq(false),
+                users::totp_last_step.eq(0),
+            ))
+            .filter(users::id.eq(id))
+            .execute(conn)?;
 
         Ok(())
     }
 
-    pub fn delete(conn: &PgConnection, id: Uuid) -> Result<(), Box<dyn Error>> {
-        diesel::delete(users::table).filter(users::id.eq(id)).execute(conn)?;
+    pub fn enable_totp(conn: &PgConnection, id: Uuid, step: i64) -> Result<(), AppError> {
+        diesel::update(users::table)
+            .set((
+                users::totp_enabled.eq(true),
+                users::totp_last_step.eq(step),
+                users::modified_at.eq(Utc::now()),
+            ))
+            .filter(users::id.eq(id))
+            .filter(users::totp_secret.is_not_null())
+            .execute(conn)?;
 
         Ok(())
     }
+
+    /// Turns two-factor off and throws away the secret and any recovery codes.
+    pub fn disable_totp(conn: &PgConnection, id: Uuid) -> Result<(), AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            diesel::update(users::table)
+                .set((
+                    users::totp_secret.eq(None::<String>),
+                    users::totp_enabled.eq(false),
+                    users::totp_last_step.eq(0),
+                    users::modified_at.eq(Utc::now()),
+                ))
+                .filter(users::id.eq(id))
+                .execute(conn)?;
+
+            RecoveryCode::delete_for_user(conn, id)
+        })
+    }
+
+    /// Checks a TOTP code against the user's secret, enrolled or pending. Returns the time step it
+    /// matched.
+    pub fn check_totp(&self, code: &str) -> Option<i64> {
+        let secret = self.totp_secret.as_ref()?;
+        totp::verify(secret.as_str(), code, Utc::now().timestamp() as u64).map(|step| step as i64)
+    }
+
+    /// Checks the second step of a login: a TOTP code or, failing that, a recovery code, which is
+    /// used up. A TOTP code is only accepted once.
+    pub fn check_second_factor(&self, conn: &PgConnection, code: &str) -> Result<bool, AppError> {
+        if let Some(step) = self.check_totp(code) {
+            let count = diesel::update(users::table)
+                .set(users::totp_last_step.eq(step))
+                .filter(users::id.eq(self.id))
+                .filter(users::totp_last_step.lt(step))
+                .execute(conn)?;
+
+            return Ok(count == 1);
+        }
+
+        RecoveryCode::redeem(conn, self.id, code)
+    }
+
+    /// Deletes the user along with their API tokens. Users that still own stations or entries
+    /// can't be deleted and should be disabled instead.
+    pub fn delete(conn: &PgConnection, id: Uuid) -> Result<(), AppError> {
+        conn.transaction::<_, AppError, _>(|| {
+            diesel::delete(api_tokens::table).filter(api_tokens::user_id.eq(id)).execute(conn)?;
+            let count = diesel::delete(users::table).filter(users::id.eq(id)).execute(conn)?;
+
+            match count {
+                0 => Err(AppError::NotFound),
+                _ => Ok(()),
+            }
+        })
+    }
 }
 
+#[derive(Debug)]
+pub enum LoginError {
+    InvalidCredentials,
+    Disabled,
+    Database(diesel::result::Error),
+    Hash(BcryptError),
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginError::InvalidCredentials => write!(f, "Invalid user name or password"),
+            LoginError::Disabled => write!(f, "User is disabled"),
+            LoginError::Database(err) => write!(f, "Database error: {}", err),
+            LoginError::Hash(err) => write!(f, "Password hash error: {}", err),
+        }
+    }
+}
+
+impl Error for LoginError {}
+
 #[derive(AsChangeset)]
 #[table_name = "users"]
 pub struct UserUpdateSet {
@@ -73,15 +273,17 @@ pub struct UserUpdateSet {
     pub password: Option<String>,
     pub enabled: Option<bool>,
     pub modified_at: DateTime<Utc>,
+    pub role: Option<String>,
 }
 
 impl UserUpdateSet {
-    pub fn new(name: Option<String>, password: Option<String>, enabled: Option<bool>) -> UserUpdateSet {
+    pub fn new(name: Option<String>, password: Option<String>, enabled: Option<bool>, role: Option<Role>) -> UserUpdateSet {
         return UserUpdateSet {
             name,
             password,
             enabled,
             modified_at: Utc::now(),
+            role: role.map(|r| r.as_str().to_string()),
         };
     }
 }
@@ -91,6 +293,8 @@ pub struct UserJson {
     pub id: Uuid,
     pub name: String,
     pub enabled: bool,
+    pub role: Role,
+    pub two_factor_enabled: bool,
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
 }
@@ -101,6 +305,8 @@ impl UserJson {
             id: user.id.to_owned(),
             name: user.name.to_owned(),
             enabled: user.enabled,
+            role: user.role(),
+            two_factor_enabled: user.totp_enabled,
             created_at: user.created_at.clone(),
             modified_at: user.modified_at.clone(),
         }
@@ -112,7 +318,6 @@ mod tests {
     use std::env;
     use std::sync::Once;
 
-    use bcrypt::{DEFAULT_COST, hash};
     use dotenv::dotenv;
 
     use super::*;
@@ -125,6 +330,12 @@ mod tests {
         });
     }
 
+    #[test]
+    fn unknown_user_hash_costs_as_much_as_a_real_one() {
+        assert!(UNKNOWN_USER_HASH.starts_with(&format!("$2b${}$", DEFAULT_COST)));
+        assert!(!verify("hunter2", UNKNOWN_USER_HASH).unwrap());
+    }
+
     /*
      * These really are integration tests, not unit tests. I've marked them all
      * ignored as they shouldn't be called during the normal test runs, and only
@@ -146,6 +357,10 @@ mod tests {
             enabled: true,
             created_at: Utc::now(),
             modified_at: Utc::now(),
+            role: Role::Admin.as_str().to_string(),
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: 0,
         };
         User::create(&connection, user).expect("Failed to create user.");
         let expected_name = vec!["testuser".to_string()];
@@ -164,10 +379,10 @@ mod tests {
             Some("testuser2".to_string()),
             None,
             None,
+            None,
         );
         User::update(&connection, id, update_user).unwrap();
-        let result = User::read(&connection, id).unwrap();
-        let user = result.get(0).unwrap();
+        let user = User::read(&connection, id).unwrap();
         let expected_name = "testuser2".to_string();
         assert_eq!(expected_name, user.name);
     }
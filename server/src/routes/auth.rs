@@ -1,64 +1,309 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::Connection;
 use diesel::PgConnection;
-use log::error;
+use log::{debug, warn};
 use rocket::http::Status;
+use rocket::State;
 use rocket_contrib::json::Json;
+use uuid::Uuid;
 
 use crate::DbConn;
-use crate::models::api_token::ApiToken;
-use crate::models::user::User;
-use crate::utils::jwt::generate_jwt;
-use crate::models::auth::Auth;
+use crate::error::AppError;
+use crate::models::api_token::{ApiToken, DeviceInfo};
+use crate::models::invite::Invite;
+use crate::models::login_attempt::{LoginAttempt, LoginPolicy};
+use crate::models::refresh_token::RefreshToken;
+use crate::models::user::{LoginError, User};
+use crate::utils::client::{ClientIp, UserAgent};
+use crate::utils::jwk::JwkSet;
+use crate::utils::jwt::{generate_challenge, generate_jwt, Keyring, read_challenge, TokenLifetimes};
+use crate::models::auth::{Auth, SessionAuth};
+use crate::routes::parse_id;
+use crate::utils::password::PasswordPolicy;
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
     name: String,
     password: String,
+    device_name: Option<String>,
 }
 
+const TWO_FACTOR_CHALLENGE_MINUTES: i64 = 5;
+
 #[derive(Serialize)]
 pub struct LoginResponse {
     jwt: String,
+    refresh_token: String,
+    expires_in: i64,
 }
 
-#[post("/auth/login", data = "<payload>")]
-pub fn login(conn: DbConn, payload: Json<LoginRequest>) -> Result<Json<LoginResponse>, Status> {
-    let user_result = User::validate(&conn as &PgConnection, payload.name.to_string(), payload.password.to_string());
+/// Handed back instead of tokens when the user has two-factor enabled. The challenge goes to
+/// `/auth/login/2fa` with a code.
+#[derive(Serialize)]
+pub struct TwoFactorChallenge {
+    two_factor_required: bool,
+    challenge: String,
+    expires_in: i64,
+}
 
-    // Generate token
-    let api_token_result = match user_result {
-        Ok(user) => ApiToken::create_for_user(&conn as &PgConnection, user),
-        Err(err) => {
-            // todo!("Detect the type of errors so we ignore bad login errors.")
-            error!("{}", err.to_string());
-            return Err(Status::Unauthorized);
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResult {
+    Tokens(LoginResponse),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    invite_code: String,
+    name: String,
+    password: String,
+    device_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorLoginRequest {
+    challenge: String,
+    code: String,
+    device_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// A signed-in session as shown to its owner. `current` marks the one making the request.
+#[derive(Serialize)]
+pub struct SessionJson {
+    id: Uuid,
+    device_name: Option<String>,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: DateTime<Utc>,
+    current: bool,
+}
+
+impl SessionJson {
+    fn new(api_token: ApiToken, current_id: Uuid) -> Self {
+        SessionJson {
+            id: api_token.id,
+            current: api_token.id == current_id,
+            device_name: api_token.device_name,
+            user_agent: api_token.user_agent,
+            ip_address: api_token.ip_address,
+            created_at: api_token.created_at,
+            last_used_at: api_token.last_used_at,
         }
+    }
+}
+
+// Issues a fresh access JWT and the next refresh token for a session.
+fn issue_tokens(conn: &PgConnection, keyring: &Keyring, lifetimes: &TokenLifetimes, api_token: &ApiToken) -> Result<LoginResponse, AppError> {
+    let jwt = generate_jwt(keyring, api_token, lifetimes.access)?;
+    let (_, refresh_token) = RefreshToken::issue(conn, api_token, lifetimes.refresh)?;
+
+    Ok(LoginResponse {
+        jwt,
+        refresh_token,
+        expires_in: lifetimes.access.num_seconds(),
+    })
+}
+
+#[post("/auth/login", data = "<payload>")]
+pub fn login(conn: DbConn, policy: State<LoginPolicy>, keyring: State<Keyring>, lifetimes: State<TokenLifetimes>, client_ip: ClientIp, user_agent: UserAgent, payload: Json<LoginRequest>) -> Result<Json<LoginResult>, AppError> {
+    let pg_conn = &conn as &PgConnection;
+
+    if LoginAttempt::is_locked_out(pg_conn, &policy, payload.name.as_str(), client_ip.0)? {
+        return Err(AppError::TooManyRequests);
+    }
+
+    let user = match User::validate(pg_conn, payload.name.to_string(), payload.password.to_string()) {
+        Ok(user) => user,
+        Err(LoginError::InvalidCredentials) => return Err(reject(pg_conn, &payload.name, client_ip.0, AppError::Unauthorized)),
+        Err(LoginError::Disabled) => return Err(reject(pg_conn, &payload.name, client_ip.0, AppError::Forbidden)),
+        Err(err) => return Err(AppError::Internal(err.to_string())),
     };
 
-    // todo!("Make this actually use a jwt.")
-    let jwt_result = match api_token_result {
-        Ok(api_token) => generate_jwt(api_token),
+    // The login isn't recorded as a success until the second factor checks out, otherwise a
+    // correct password would reset the lockout for guessing codes.
+    if user.totp_enabled {
+        let challenge = generate_challenge(&keyring, user.id, Duration::minutes(TWO_FACTOR_CHALLENGE_MINUTES))?;
+        return Ok(Json(LoginResult::TwoFactorRequired(TwoFactorChallenge {
+            two_factor_required: true,
+            challenge,
+            expires_in: TWO_FACTOR_CHALLENGE_MINUTES * 60,
+        })));
+    }
+
+    LoginAttempt::record(pg_conn, payload.name.as_str(), Some(user.id), client_ip.0, true)?;
+
+    let device = device_info(client_ip, user_agent, &payload.device_name);
+    let response = start_session(pg_conn, &keyring, &lifetimes, user, device)?;
+    Ok(Json(LoginResult::Tokens(response)))
+}
+
+/// Completes a login for a user with two-factor enabled, given the challenge from
+/// `/auth/login` and a TOTP or recovery code. Failures count towards the same lockout as
+/// passwords.
+#[post("/auth/login/2fa", data = "<payload>")]
+pub fn login_two_factor(conn: DbConn, policy: State<LoginPolicy>, keyring: State<Keyring>, lifetimes: State<TokenLifetimes>, client_ip: ClientIp, user_agent: UserAgent, payload: Json<TwoFactorLoginRequest>) -> Result<Json<LoginResponse>, AppError> {
+    let pg_conn = &conn as &PgConnection;
+
+    let user_id = match read_challenge(&keyring, payload.challenge.as_str()) {
+        Ok(id) => id,
         Err(err) => {
-            error!("{}", err.to_string());
-            return Err(Status::InternalServerError);
+            debug!("{}", err.to_string());
+            return Err(AppError::Unauthorized);
         }
     };
+    let user = match User::read(pg_conn, user_id) {
+        Ok(u) => u,
+        Err(AppError::NotFound) => return Err(AppError::Unauthorized),
+        Err(err) => return Err(err),
+    };
+    if !user.enabled {
+        return Err(AppError::Forbidden);
+    }
 
-    match jwt_result {
-        Ok(jwt) => Ok(Json(LoginResponse { jwt })),
-        Err(err) => {
-            error!("{}", err.to_string());
-            return Err(Status::InternalServerError);
-        }
+    if LoginAttempt::is_locked_out(pg_conn, &policy, user.name.as_str(), client_ip.0)? {
+        return Err(AppError::TooManyRequests);
+    }
+
+    if !user.check_second_factor(pg_conn, payload.code.as_str())? {
+        return Err(reject(pg_conn, &user.name, client_ip.0, AppError::Unauthorized));
     }
+
+    LoginAttempt::record(pg_conn, user.name.as_str(), Some(user.id), client_ip.0, true)?;
+
+    let device = device_info(client_ip, user_agent, &payload.device_name);
+    Ok(Json(start_session(pg_conn, &keyring, &lifetimes, user, device)?))
 }
 
-#[get("/auth/logout")]
-pub fn logout(conn: DbConn, auth: &Auth) -> Status {
-    match ApiToken::invalidate(&conn as &PgConnection, auth.api_token.id) {
-        Ok(_) => Status::Ok,
-        Err(err) => {
-            error!("{}", err.to_string());
-            Status::InternalServerError
+/// Creates an account with an invite code and logs it straight in. The account gets the role the
+/// invite was made with. An unknown, expired or used up code is a 403; a taken name a 409, and
+/// doesn't use up the code.
+#[post("/auth/register", data = "<payload>")]
+pub fn register(conn: DbConn, password_policy: State<PasswordPolicy>, keyring: State<Keyring>, lifetimes: State<TokenLifetimes>, client_ip: ClientIp, user_agent: UserAgent, payload: Json<RegisterRequest>) -> Result<Json<LoginResponse>, AppError> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::invalid("name", "must not be empty"));
+    }
+    password_policy.check(payload.password.as_str(), name)
+        .map_err(|problems| AppError::invalid_field("password", problems))?;
+
+    let pg_conn = &conn as &PgConnection;
+    let user = pg_conn.transaction::<_, AppError, _>(|| {
+        let invite = match Invite::redeem(pg_conn, payload.invite_code.trim())? {
+            Some(i) => i,
+            None => return Err(AppError::Forbidden),
+        };
+
+        let user = User::new(name.to_string(), payload.password.as_str(), invite.role())?;
+        User::create(pg_conn, user)
+    })?;
+
+    let device = device_info(client_ip, user_agent, &payload.device_name);
+    Ok(Json(start_session(pg_conn, &keyring, &lifetimes, user, device)?))
+}
+
+fn device_info(client_ip: ClientIp, user_agent: UserAgent, device_name: &Option<String>) -> DeviceInfo {
+    DeviceInfo {
+        user_agent: user_agent.0,
+        ip_address: client_ip.0.map(|ip| ip.to_string()),
+        device_name: device_name.as_ref().map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+    }
+}
+
+// Creates the session for a user who has fully logged in and issues its first tokens.
+fn start_session(conn: &PgConnection, keyring: &Keyring, lifetimes: &TokenLifetimes, user: User, device: DeviceInfo) -> Result<LoginResponse, AppError> {
+    let api_token = ApiToken::create_for_user(conn, user, device)?;
+
+    issue_tokens(conn, keyring, lifetimes, &api_token)
+}
+
+/// Trades a refresh token for a new access JWT and refresh token. Each refresh token works once;
+/// presenting one a second time means it leaked, so every session the user has is revoked.
+#[post("/auth/refresh", data = "<payload>")]
+pub fn refresh(conn: DbConn, keyring: State<Keyring>, lifetimes: State<TokenLifetimes>, payload: Json<RefreshRequest>) -> Result<Json<LoginResponse>, AppError> {
+    let pg_conn = &conn as &PgConnection;
+
+    let result = pg_conn.transaction::<_, AppError, _>(|| {
+        let token = match RefreshToken::find_by_secret(pg_conn, payload.refresh_token.as_str())? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let auth = Auth::new(pg_conn, token.api_token_id)?;
+        let api_token = match auth.session() {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        // Not an error, so the revocation is committed.
+        if !RefreshToken::mark_used(pg_conn, token.id)? {
+            warn!("Refresh token {} was reused, revoking all sessions for user {}", token.id, auth.user.id);
+            ApiToken::invalidate_all_for_user(pg_conn, &auth.user)?;
+            return Ok(None);
         }
+
+        if token.expires_at < Utc::now() || api_token.force_invalid || !auth.user.enabled {
+            return Ok(None);
+        }
+
+        ApiToken::touch(pg_conn, api_token.id)?;
+        Ok(Some(issue_tokens(pg_conn, &keyring, &lifetimes, api_token)?))
+    })?;
+
+    match result {
+        Some(response) => Ok(Json(response)),
+        None => Err(AppError::Unauthorized),
+    }
+}
+
+// Records a failed attempt and hands back the error to refuse the login with.
+fn reject(conn: &PgConnection, name: &str, client_ip: Option<IpAddr>, error: AppError) -> AppError {
+    match LoginAttempt::record(conn, name, None, client_ip, false) {
+        Ok(_) => error,
+        Err(err) => err,
     }
 }
+
+#[get("/auth/logout")]
+pub fn logout(conn: DbConn, auth: SessionAuth) -> Result<Status, AppError> {
+    ApiToken::invalidate(&conn as &PgConnection, auth.api_token.id)?;
+    Ok(Status::Ok)
+}
+
+/// Revokes every session the user has, including the one making the request.
+#[get("/auth/logout/all")]
+pub fn logout_all(conn: DbConn, auth: SessionAuth) -> Result<Status, AppError> {
+    ApiToken::invalidate_all_for_user(&conn as &PgConnection, auth.user)?;
+    Ok(Status::Ok)
+}
+
+/// Lists the caller's live sessions. A session counts as live until it goes unused for longer
+/// than a refresh token lasts.
+#[get("/auth/sessions")]
+pub fn get_sessions(conn: DbConn, lifetimes: State<TokenLifetimes>, auth: SessionAuth) -> Result<Json<Vec<SessionJson>>, AppError> {
+    let since = Utc::now() - lifetimes.refresh;
+    let tokens = ApiToken::read_active_for_user(&conn as &PgConnection, auth.user.id, since)?;
+
+    Ok(Json(tokens.into_iter().map(|t| SessionJson::new(t, auth.api_token.id)).collect()))
+}
+
+#[delete("/auth/sessions/<id>")]
+pub fn revoke_session(conn: DbConn, auth: SessionAuth, id: String) -> Result<Status, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    ApiToken::invalidate_for_user(&conn as &PgConnection, auth.user.id, parsed_id)?;
+    Ok(Status::Ok)
+}
+
+/// Public keys for verifying our JWTs, for other services. Only asymmetric keys are listed.
+#[get("/.well-known/jwks.json")]
+pub fn jwks(keyring: State<Keyring>) -> Json<JwkSet> {
+    Json(keyring.jwks())
+}
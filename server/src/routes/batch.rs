@@ -0,0 +1,215 @@
+use diesel::Connection;
+use diesel::PgConnection;
+use rocket::State;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::json::Json;
+use uuid::Uuid;
+
+use crate::DbConn;
+use crate::error::{AppError, ErrorBody};
+use crate::models::auth::WriterAuth;
+use crate::models::precipitation_log::PrecipitationLog;
+use crate::routes::log::{create_checked, read_for_update, save_changes, CreateEntryRequest, PatchEntryRequest};
+use crate::utils::etag::IfMatch;
+use crate::utils::idempotency::{Idempotent, IdempotencyKey};
+use crate::utils::validation::EntryPolicy;
+
+const MAX_OPERATIONS: usize = 1000;
+
+/// What a batch does when an operation fails. `atomic` rolls the whole batch back, `independent`
+/// only that operation.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchMode {
+    Atomic,
+    Independent,
+}
+
+impl Default for BatchMode {
+    fn default() -> Self {
+        BatchMode::Atomic
+    }
+}
+
+/// One operation in a batch, tagged by `op`. Updates change only the fields given, like PATCH,
+/// and `if_match` works like the `If-Match` header.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    Create {
+        entry: CreateEntryRequest,
+    },
+    Update {
+        id: Uuid,
+        changes: PatchEntryRequest,
+        if_match: Option<String>,
+    },
+    Delete {
+        id: Uuid,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub mode: BatchMode,
+    pub operations: Vec<BatchOperation>,
+}
+
+/// How one operation went. `entry` is the entry as stored after a create or update, `error` the
+/// same body a single request would have failed with.
+#[derive(Serialize)]
+pub struct BatchResult {
+    pub index: usize,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<PrecipitationLog>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+/// `committed` is false when an atomic batch was rolled back. Its results then end at the
+/// operation that failed, and the ones before it weren't kept either.
+#[derive(Serialize)]
+pub struct BatchReport {
+    pub committed: bool,
+    pub results: Vec<BatchResult>,
+}
+
+/// Runs a list of creates, updates and deletes in one transaction, in order. An atomic batch
+/// that fails responds with the failing operation's status; otherwise the status is 200 and each
+/// result has its own.
+#[post("/logs/batch", data = "<batch>")]
+pub fn run_batch(conn: DbConn, auth: WriterAuth, policy: State<EntryPolicy>, idempotency: IdempotencyKey, batch: Json<BatchRequest>) -> Result<Idempotent<status::Custom<Json<BatchReport>>>, AppError> {
+    check_size(&batch)?;
+
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &*batch, || run_operations(pg_conn, auth.user.id, &policy, &batch))
+}
+
+fn check_size(batch: &BatchRequest) -> Result<(), AppError> {
+    if batch.operations.is_empty() {
+        return Err(AppError::invalid("operations", "must not be empty"));
+    }
+    if batch.operations.len() > MAX_OPERATIONS {
+        return Err(AppError::invalid("operations", &format!("must have at most {} operations", MAX_OPERATIONS)));
+    }
+    Ok(())
+}
+
+fn run_operations(conn: &PgConnection, user_id: Uuid, policy: &EntryPolicy, batch: &BatchRequest) -> Result<status::Custom<Json<BatchReport>>, AppError> {
+    let mut results = Vec::with_capacity(batch.operations.len());
+    let outcome = conn.transaction::<_, AppError, _>(|| {
+        for (index, operation) in batch.operations.iter().enumerate() {
+            let result = match batch.mode {
+                BatchMode::Atomic => apply(conn, user_id, policy, operation),
+                // Each operation gets a savepoint, so a failure only undoes that one.
+                BatchMode::Independent => conn.transaction(|| apply(conn, user_id, policy, operation)),
+            };
+
+            match result {
+                Ok(entry) => results.push(BatchResult {
+                    index,
+                    status: Status::Ok.code,
+                    entry,
+                    error: None,
+                }),
+                Err(err) => {
+                    err.log();
+                    results.push(BatchResult {
+                        index,
+                        status: err.status().code,
+                        entry: None,
+                        error: Some(err.body()),
+                    });
+                    if batch.mode == BatchMode::Atomic {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        Ok(())
+    });
+
+    match outcome {
+        Ok(()) => Ok(status::Custom(Status::Ok, Json(BatchReport { committed: true, results }))),
+        // An operation failed and the batch was rolled back; anything else is the commit failing.
+        Err(err) if batch.mode == BatchMode::Atomic && results.last().map_or(false, |r| r.error.is_some()) => {
+            Ok(status::Custom(err.status(), Json(BatchReport { committed: false, results })))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn apply(conn: &PgConnection, user_id: Uuid, policy: &EntryPolicy, operation: &BatchOperation) -> Result<Option<PrecipitationLog>, AppError> {
+    match operation {
+        BatchOperation::Create { entry } => {
+            let new_entry = entry.clone().into_precipitation_log(user_id);
+            create_checked(conn, user_id, policy, &new_entry).map(Some)
+        }
+        BatchOperation::Update { id, changes, if_match } => {
+            let existing = read_for_update(conn, user_id, *id, &IfMatch(if_match.clone()))?;
+            let updated = changes.applied_to(&existing);
+            save_changes(conn, user_id, policy, &existing, &updated).map(Some)
+        }
+        BatchOperation::Delete { id } => {
+            PrecipitationLog::soft_delete(conn, user_id, *id, user_id)?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: &str) -> Result<BatchRequest, serde_json::Error> {
+        serde_json::from_str(body)
+    }
+
+    #[test]
+    fn parses_tagged_operations() {
+        let id = Uuid::new_v4();
+        let batch = parse(&format!(r#"{{"operations": [
+            {{"op": "create", "entry": {{"station_id": "{id}", "measurement": 1.5, "logged_at": "2020-01-01T00:00:00Z", "notes": null, "ptype": "liquid", "anomaly": false}}}},
+            {{"op": "update", "id": "{id}", "changes": {{"measurement": 2.5}}, "if_match": "\"1\""}},
+            {{"op": "delete", "id": "{id}"}}
+        ]}}"#, id = id)).unwrap();
+
+        assert_eq!(BatchMode::Atomic, batch.mode);
+        match &batch.operations[..] {
+            [BatchOperation::Create { entry }, BatchOperation::Update { id: updated, changes, if_match }, BatchOperation::Delete { id: deleted }] => {
+                assert_eq!(id, entry.station_id);
+                assert_eq!(id, *updated);
+                assert_eq!(Some(2.5), changes.measurement);
+                assert_eq!(Some("\"1\"".to_string()), *if_match);
+                assert_eq!(id, *deleted);
+            }
+            _ => panic!("Operations weren't parsed in order"),
+        }
+
+        let independent = parse(r#"{"mode": "independent", "operations": []}"#).unwrap();
+        assert_eq!(BatchMode::Independent, independent.mode);
+    }
+
+    #[test]
+    fn rejects_unknown_operations() {
+        assert!(parse(&format!(r#"{{"operations": [{{"op": "purge", "id": "{}"}}]}}"#, Uuid::new_v4())).is_err());
+        assert!(parse(&format!(r#"{{"operations": [{{"id": "{}"}}]}}"#, Uuid::new_v4())).is_err());
+        assert!(parse(r#"{"mode": "sometimes", "operations": []}"#).is_err());
+    }
+
+    #[test]
+    fn limits_the_number_of_operations() {
+        let batch = |count| BatchRequest {
+            mode: BatchMode::Atomic,
+            operations: (0..count).map(|_| BatchOperation::Delete { id: Uuid::new_v4() }).collect(),
+        };
+
+        assert!(check_size(&batch(0)).is_err());
+        assert!(check_size(&batch(1)).is_ok());
+        assert!(check_size(&batch(MAX_OPERATIONS)).is_ok());
+        assert!(check_size(&batch(MAX_OPERATIONS + 1)).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::Read;
+
+use chrono_tz::Tz;
+use diesel::Connection;
+use diesel::PgConnection;
+use rocket::{Data, State};
+use rocket::http::Status;
+use rocket::request::Form;
+use rocket::response::status;
+use rocket_contrib::json::Json;
+use uuid::Uuid;
+
+use crate::DbConn;
+use crate::error::AppError;
+use crate::models::auth::WriterAuth;
+use crate::models::precipitation_log::PrecipitationLog;
+use crate::models::station::Station;
+use crate::utils::csv_import::{ColumnMapping, ImportOptions, parse_entries, RowError};
+use crate::utils::idempotency::{Idempotent, IdempotencyKey};
+use crate::utils::validation::EntryPolicy;
+
+const IMPORT_LIMIT: u64 = 10 * 1024 * 1024;
+
+/// Query string accepted by the CSV import. The `*_column` fields rename the header each entry
+/// field is read from, `station` is used for rows without one, and `date_format` is a chrono
+/// format string for `logged_at` when it isn't RFC 3339.
+#[derive(FromForm, Clone)]
+pub struct ImportQuery {
+    pub dry_run: Option<bool>,
+    pub measurement_column: Option<String>,
+    pub logged_at_column: Option<String>,
+    pub ptype_column: Option<String>,
+    pub notes_column: Option<String>,
+    pub anomaly_column: Option<String>,
+    pub station_column: Option<String>,
+    pub station: Option<String>,
+    pub date_format: Option<String>,
+    pub tz: Option<String>,
+    pub delimiter: Option<String>,
+}
+
+impl ImportQuery {
+    fn options(&self) -> Result<ImportOptions, String> {
+        let defaults = ColumnMapping::default();
+        let columns = ColumnMapping {
+            measurement: self.measurement_column.clone().unwrap_or(defaults.measurement),
+            logged_at: self.logged_at_column.clone().unwrap_or(defaults.logged_at),
+            ptype: self.ptype_column.clone().unwrap_or(defaults.ptype),
+            notes: self.notes_column.clone().unwrap_or(defaults.notes),
+            anomaly: self.anomaly_column.clone().unwrap_or(defaults.anomaly),
+            station: self.station_column.clone().unwrap_or(defaults.station),
+        };
+
+        let delimiter = match self.delimiter.as_ref().map(|d| d.as_bytes()) {
+            None => b',',
+            Some([d]) => *d,
+            Some(_) => return Err("delimiter must be a single byte".to_string()),
+        };
+
+        Ok(ImportOptions {
+            columns,
+            delimiter,
+            date_format: self.date_format.clone(),
+            time_zone: self.tz.as_ref().map(|tz| tz.parse::<Tz>()).transpose()?,
+            default_station: self.station.as_ref().map(|s| Uuid::parse_str(s)).transpose().map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: usize,
+    pub valid_rows: usize,
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// Imports entries from a CSV upload. Nothing is written unless every row is valid; with
+/// `dry_run` the rows are only checked and the report says what would have happened.
+#[post("/logs/import?<query..>", data = "<data>")]
+pub fn import_entries(conn: DbConn, auth: WriterAuth, policy: State<EntryPolicy>, idempotency: IdempotencyKey, query: Form<ImportQuery>, data: Data) -> Result<Idempotent<status::Custom<Json<ImportReport>>>, AppError> {
+    let options = query.options().map_err(AppError::BadRequest)?;
+
+    // Read one byte past the limit so an upload that's too big is refused, not cut short.
+    let mut bytes = Vec::new();
+    data.open().take(IMPORT_LIMIT + 1).read_to_end(&mut bytes)
+        .map_err(|err| AppError::BadRequest(format!("Couldn't read the upload: {}", err)))?;
+    if bytes.len() as u64 > IMPORT_LIMIT {
+        return Err(AppError::PayloadTooLarge);
+    }
+    let body = String::from_utf8(bytes)
+        .map_err(|err| AppError::BadRequest(format!("Couldn't read the upload: {}", err)))?;
+
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &body, || {
+        let stations = Station::read_all(pg_conn, auth.user.id)?;
+
+        let parsed = parse_entries(body.as_str(), &options, &policy, auth.user.id, &stations);
+        let dry_run = query.dry_run.unwrap_or(false);
+        let mut report = ImportReport {
+            dry_run,
+            rows: parsed.rows,
+            valid_rows: parsed.entries.len(),
+            imported: 0,
+            errors: parsed.errors,
+        };
+
+        if dry_run {
+            return Ok(status::Custom(Status::Ok, Json(report)));
+        }
+        if !report.errors.is_empty() {
+            return Ok(status::Custom(Status::UnprocessableEntity, Json(report)));
+        }
+
+        report.imported = pg_conn.transaction::<_, AppError, _>(|| {
+            for entry in parsed.entries.iter() {
+                PrecipitationLog::create(pg_conn, entry, auth.user.id)?;
+            }
+            Ok(parsed.entries.len())
+        })?;
+
+        Ok(status::Custom(Status::Ok, Json(report)))
+    })
+}
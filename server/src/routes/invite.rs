@@ -0,0 +1,60 @@
+use chrono::Duration;
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+use crate::DbConn;
+use crate::error::AppError;
+use crate::models::auth::AdminAuth;
+use crate::models::invite::Invite;
+use crate::models::user::Role;
+use crate::routes::parse_id;
+
+const DEFAULT_INVITE_DAYS: i64 = 7;
+const MAX_INVITE_DAYS: i64 = 365;
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    pub role: Option<Role>,
+    pub max_uses: Option<i32>,
+    pub expires_in_days: Option<i64>,
+}
+
+/// The new invite along with its code. This is the only time the code is returned.
+#[derive(Serialize)]
+pub struct CreateInviteResponse {
+    pub invite: Invite,
+    pub code: String,
+}
+
+#[get("/invites")]
+pub fn get_invites(conn: DbConn, _admin: AdminAuth) -> Result<Json<Vec<Invite>>, AppError> {
+    Ok(Json(Invite::read_all(&conn as &PgConnection)?))
+}
+
+/// Mints an invite. By default it registers one observer and lasts a week; it can last a year at
+/// most.
+#[post("/invites", data = "<request>")]
+pub fn create_invite(conn: DbConn, admin: AdminAuth, request: Json<CreateInviteRequest>) -> Result<Json<CreateInviteResponse>, AppError> {
+    let max_uses = request.max_uses.unwrap_or(1);
+    let days = request.expires_in_days.unwrap_or(DEFAULT_INVITE_DAYS);
+    if max_uses < 1 {
+        return Err(AppError::invalid("max_uses", "must be at least 1"));
+    }
+    if days < 1 || days > MAX_INVITE_DAYS {
+        return Err(AppError::invalid("expires_in_days", &format!("must be between 1 and {}", MAX_INVITE_DAYS)));
+    }
+
+    let role = request.role.unwrap_or(Role::Observer);
+    let (invite, code) = Invite::create(&conn as &PgConnection, role, max_uses, Duration::days(days), admin.user.id)?;
+
+    Ok(Json(CreateInviteResponse { invite, code }))
+}
+
+#[delete("/invites/<id>")]
+pub fn delete_invite(conn: DbConn, _admin: AdminAuth, id: String) -> Result<Status, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    Invite::delete(&conn as &PgConnection, parsed_id)?;
+    Ok(Status::Ok)
+}
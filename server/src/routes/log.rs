@@ -1,118 +1,434 @@
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use diesel::PgConnection;
-use log::{debug, error};
-use rocket::http::Status;
+use rocket::http::{Accept, Status};
+use rocket::State;
+use rocket::request::Form;
+use rocket::response::{Content, Stream};
 use rocket_contrib::json::Json;
 use uuid::Uuid;
 
 use crate::DbConn;
-use crate::models::auth::Auth;
-use crate::models::precipitation_log::{PrecipitationLog, PrecipitationType};
+use crate::error::AppError;
+use crate::models::auth::{AdminAuth, ReaderAuth, WriterAuth};
+use crate::models::entry_revision::EntryRevision;
+use crate::models::precipitation_log::{EntryCursor, EntryFilter, PrecipitationLog, PrecipitationType, SortOrder};
+use crate::models::station::Station;
+use crate::models::summary::{Period, PrecipitationSummary};
+use crate::routes::parse_id;
+use crate::utils::etag::{IfMatch, Tagged};
+use crate::utils::export::{EntryExport, ExportFormat};
+use crate::utils::idempotency::{Idempotent, IdempotencyKey};
+use crate::utils::validation::EntryPolicy;
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CreateEntryRequest {
+    pub station_id: Uuid,
     pub measurement: f32,
     pub logged_at: DateTime<Utc>,
     pub notes: Option<String>,
-    pub ptype: i16,
+    pub ptype: PrecipitationType,
     pub anomaly: bool,
 }
 
 impl CreateEntryRequest {
-    pub fn into_precipitation_log(self) -> PrecipitationLog {
+    pub fn into_precipitation_log(self, user_id: Uuid) -> PrecipitationLog {
         PrecipitationLog::new(
+            user_id,
+            self.station_id,
             self.measurement,
             self.logged_at,
-            PrecipitationType::from_i16(self.ptype),
+            self.ptype,
             self.notes,
             self.anomaly,
         )
     }
 }
 
-#[get("/logs/entries")]
-pub fn get_all_entries(conn: DbConn, _auth: &Auth) -> Result<Json<Vec<PrecipitationLog>>, Status> {
-    match PrecipitationLog::read_all(&conn as &PgConnection) {
-        Ok(entries) => Ok(Json(entries)),
-        Err(err) => {
-            error!("{}", err.to_string());
-            Err(Status::InternalServerError)
+/// Body of a PATCH. Only the fields present are changed, and a `null` for `notes` clears them.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PatchEntryRequest {
+    pub station_id: Option<Uuid>,
+    pub measurement: Option<f32>,
+    pub logged_at: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Option<String>>,
+    pub ptype: Option<PrecipitationType>,
+    pub anomaly: Option<bool>,
+}
+
+impl PatchEntryRequest {
+    pub fn apply(&self, entry: &mut PrecipitationLog) {
+        if let Some(station_id) = self.station_id {
+            entry.station_id = station_id;
+        }
+        if let Some(measurement) = self.measurement {
+            entry.measurement = measurement;
+        }
+        if let Some(logged_at) = self.logged_at {
+            entry.logged_at = logged_at;
+        }
+        if let Some(ref notes) = self.notes {
+            entry.notes = notes.clone();
+        }
+        if let Some(ptype) = self.ptype {
+            entry.ptype = ptype;
+        }
+        if let Some(anomaly) = self.anomaly {
+            entry.anomaly = anomaly;
         }
     }
+
+    /// A copy of `entry` with the changes applied.
+    pub fn applied_to(&self, entry: &PrecipitationLog) -> PrecipitationLog {
+        let mut updated = entry.clone();
+        self.apply(&mut updated);
+        updated.modified_at = Utc::now();
+        updated
+    }
 }
 
-#[get("/logs/entry/<id>")]
-pub fn get_entry(conn: DbConn, _auth: &Auth, id: String) -> Result<Json<PrecipitationLog>, Status> {
-    let parsed_id = match Uuid::parse_str(id.as_str()) {
-        Ok(id) => id,
-        Err(err) => {
-            debug!("{}", err.to_string());
-            return Err(Status::BadRequest);
+// Tells a field sent as `null` apart from one left out, which `default` turns into `None`.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Query string accepted by the entry listing. Timestamps are RFC 3339, `ptype` is a precipitation
+/// type name, `sort` is `asc` or `desc` (the default) on `logged_at`, and `cursor` is the `next`
+/// value of the previous page.
+#[derive(FromForm, Clone)]
+pub struct EntryQuery {
+    pub station: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub ptype: Option<String>,
+    pub anomaly: Option<bool>,
+    pub min_measurement: Option<f32>,
+    pub max_measurement: Option<f32>,
+    pub sort: Option<String>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+impl EntryQuery {
+    pub fn filter(&self) -> Result<EntryFilter, String> {
+        Ok(EntryFilter {
+            station_id: parse_optional(&self.station, |s| Uuid::parse_str(s).map_err(|e| e.to_string()))?,
+            from: parse_optional(&self.from, parse_timestamp)?,
+            to: parse_optional(&self.to, parse_timestamp)?,
+            ptype: parse_optional(&self.ptype, |p| PrecipitationType::parse(p).ok_or_else(|| format!("Unknown ptype {}", p)))?,
+            anomaly: self.anomaly,
+            min_measurement: self.min_measurement,
+            max_measurement: self.max_measurement,
+        })
+    }
+
+    pub fn order(&self) -> Result<SortOrder, String> {
+        match self.sort.as_ref().map(|s| s.as_str()) {
+            None | Some("desc") => Ok(SortOrder::Descending),
+            Some("asc") => Ok(SortOrder::Ascending),
+            Some(other) => Err(format!("Unknown sort order {}", other)),
         }
-    };
+    }
 
-    match PrecipitationLog::read(&conn as &PgConnection, parsed_id) {
-        Ok(entry) => match entry {
-            Some(e) => Ok(Json(e)),
-            None => Err(Status::NotFound),
-        },
-        Err(err) => {
-            error!("{}", err.to_string());
-            Err(Status::InternalServerError)
+    pub fn cursor(&self) -> Result<Option<EntryCursor>, String> {
+        parse_optional(&self.cursor, |c| EntryCursor::decode(c).ok_or_else(|| "Malformed cursor".to_string()))
+    }
+
+    pub fn limit(&self) -> Result<i64, String> {
+        match self.limit.unwrap_or(DEFAULT_PAGE_SIZE) {
+            l if l < 1 || l > MAX_PAGE_SIZE => Err(format!("limit must be between 1 and {}", MAX_PAGE_SIZE)),
+            l => Ok(l),
         }
     }
 }
 
-#[post("/logs/entry", data = "<entry>")]
-pub fn create_entry(conn: DbConn, _auth: &Auth, entry: Json<CreateEntryRequest>) -> Result<Json<PrecipitationLog>, Status> {
-    let new_entry = entry.clone().into_precipitation_log();
-    match PrecipitationLog::create(&conn as &PgConnection, &new_entry) {
-        Ok(result) => Ok(Json(result)),
-        Err(err) => {
-            error!("{}", err.to_string());
-            Err(Status::InternalServerError)
-        }
+fn parse_optional<T, F>(value: &Option<String>, parse: F) -> Result<Option<T>, String>
+    where F: Fn(&str) -> Result<T, String>
+{
+    value.as_ref().map(|v| parse(v.as_str())).transpose()
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+pub struct EntryPage {
+    pub entries: Vec<PrecipitationLog>,
+    pub next: Option<String>,
+}
+
+#[get("/logs/entries?<query..>")]
+pub fn get_all_entries(conn: DbConn, auth: ReaderAuth, query: Form<EntryQuery>) -> Result<Json<EntryPage>, AppError> {
+    let params = query.filter().and_then(|f| Ok((f, query.order()?, query.cursor()?, query.limit()?)));
+    let (filter, order, cursor, limit) = params.map_err(AppError::BadRequest)?;
+
+    let (entries, next) = PrecipitationLog::search(&conn as &PgConnection, auth.user.id, &filter, order, cursor.as_ref(), limit)?;
+    Ok(Json(EntryPage {
+        entries,
+        next: next.map(|c| c.encode()),
+    }))
+}
+
+/// Streams every entry matching the listing filters as JSON, NDJSON or CSV depending on the
+/// `Accept` header. Paging parameters are ignored.
+#[get("/logs/export?<query..>")]
+pub fn export_entries(conn: DbConn, auth: ReaderAuth, accept: Option<&Accept>, query: Form<EntryQuery>) -> Result<Content<Stream<EntryExport>>, AppError> {
+    let format = ExportFormat::negotiate(accept).ok_or(AppError::NotAcceptable)?;
+
+    let params = query.filter().and_then(|f| Ok((f, query.order()?)));
+    let (filter, order) = params.map_err(AppError::BadRequest)?;
+
+    let export = EntryExport::new(conn, auth.user.id, filter, order, format);
+    Ok(Content(format.content_type(), Stream::from(export)))
+}
+
+/// Query string accepted by the summary endpoint. `period` is one of `day`, `week`, `month` or
+/// `year`. Buckets follow `tz` when it is given, otherwise the station's time zone or UTC.
+#[derive(FromForm, Clone)]
+pub struct SummaryQuery {
+    pub period: String,
+    pub station: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub tz: Option<String>,
+}
+
+#[get("/logs/summary?<query..>")]
+pub fn get_summary(conn: DbConn, auth: ReaderAuth, query: Form<SummaryQuery>) -> Result<Json<Vec<PrecipitationSummary>>, AppError> {
+    let period = Period::parse(query.period.as_str())
+        .ok_or_else(|| AppError::BadRequest(format!("Unknown period {}", query.period)))?;
+
+    let params = parse_optional(&query.station, |s| Uuid::parse_str(s).map_err(|e| e.to_string()))
+        .and_then(|station| Ok((station, parse_optional(&query.from, parse_timestamp)?, parse_optional(&query.to, parse_timestamp)?)));
+    let (station_id, from, to) = params.map_err(AppError::BadRequest)?;
+
+    let station_time_zone = match station_id {
+        Some(id) => match Station::read(&conn as &PgConnection, auth.user.id, id)? {
+            Some(station) => Some(station.time_zone),
+            None => return Err(AppError::NotFound),
+        },
+        None => None,
+    };
+
+    let time_zone = query.tz.clone().or(station_time_zone).unwrap_or_else(|| "UTC".to_string());
+    if time_zone.parse::<Tz>().is_err() {
+        return Err(AppError::BadRequest(format!("Unknown time zone {}", time_zone)));
     }
+
+    let summaries = PrecipitationSummary::read(&conn as &PgConnection, auth.user.id, period, time_zone.as_str(), station_id, from, to)?;
+    Ok(Json(summaries))
+}
+
+#[get("/logs/entry/<id>")]
+pub fn get_entry(conn: DbConn, auth: ReaderAuth, id: String) -> Result<Tagged<Json<PrecipitationLog>>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    match PrecipitationLog::read(&conn as &PgConnection, auth.user.id, parsed_id)? {
+        Some(e) => Ok(tagged(e)),
+        None => Err(AppError::NotFound),
+    }
+}
+
+#[post("/logs/entry", data = "<entry>")]
+pub fn create_entry(conn: DbConn, auth: WriterAuth, policy: State<EntryPolicy>, idempotency: IdempotencyKey, entry: Json<CreateEntryRequest>) -> Result<Idempotent<Tagged<Json<PrecipitationLog>>>, AppError> {
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &*entry, || {
+        let new_entry = entry.clone().into_precipitation_log(auth.user.id);
+        let created = create_checked(pg_conn, auth.user.id, &policy, &new_entry)?;
+        Ok(tagged(created))
+    })
 }
 
+/// Replaces an existing entry. Send the entry's `ETag` as `If-Match` to make sure nobody changed
+/// it since it was read.
 #[put("/logs/entry/<id>", data = "<entry>")]
-pub fn update_entry(conn: DbConn, _auth: &Auth, id: String, entry: Json<PrecipitationLog>) -> Result<Json<PrecipitationLog>, Status> {
-    let parsed_id = match Uuid::parse_str(id.as_str()) {
-        Ok(id) => id,
-        Err(err) => {
-            debug!("{}", err.to_string());
-            return Err(Status::BadRequest);
-        }
-    };
+pub fn update_entry(conn: DbConn, auth: WriterAuth, policy: State<EntryPolicy>, if_match: IfMatch, idempotency: IdempotencyKey, id: String, entry: Json<PrecipitationLog>) -> Result<Idempotent<Tagged<Json<PrecipitationLog>>>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
 
     if entry.id != parsed_id {
-        return Err(Status::BadRequest);
+        return Err(AppError::BadRequest("The entry's id doesn't match the path".to_string()));
     }
 
-    match PrecipitationLog::upsert(&conn as &PgConnection, &entry) {
-        Ok(e) => Ok(Json(e)),
-        Err(err) => {
-            error!("{}", err.to_string());
-            Err(Status::InternalServerError)
-        }
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &*entry, || {
+        let existing = read_for_update(pg_conn, auth.user.id, parsed_id, &if_match)?;
+        let updated = existing.replaced_by(&entry);
+        Ok(tagged(save_changes(pg_conn, auth.user.id, &policy, &existing, &updated)?))
+    })
+}
+
+/// Changes only the fields given. Takes `If-Match` the same way PUT does.
+#[patch("/logs/entry/<id>", data = "<patch>")]
+pub fn patch_entry(conn: DbConn, auth: WriterAuth, policy: State<EntryPolicy>, if_match: IfMatch, idempotency: IdempotencyKey, id: String, patch: Json<PatchEntryRequest>) -> Result<Idempotent<Tagged<Json<PrecipitationLog>>>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &*patch, || {
+        let existing = read_for_update(pg_conn, auth.user.id, parsed_id, &if_match)?;
+        let updated = patch.applied_to(&existing);
+        Ok(tagged(save_changes(pg_conn, auth.user.id, &policy, &existing, &updated)?))
+    })
+}
+
+/// Every change made to an entry, oldest first. Still available after the entry is deleted.
+#[get("/logs/entry/<id>/history")]
+pub fn get_entry_history(conn: DbConn, auth: ReaderAuth, id: String) -> Result<Json<Vec<EntryRevision>>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    let revisions = EntryRevision::read_for_entry(&conn as &PgConnection, auth.user.id, parsed_id)?;
+    if revisions.is_empty() {
+        return Err(AppError::NotFound);
     }
+    Ok(Json(revisions))
 }
 
-#[delete("/logs/entry/<id>")]
-pub fn delete_entry(conn: DbConn, _auth: &Auth, id: String) -> Result<Status, Status> {
-    let parsed_id = match Uuid::parse_str(id.as_str()) {
-        Ok(id) => id,
-        Err(err) => {
-            debug!("{}", err.to_string());
-            return Err(Status::BadRequest);
+/// Puts an entry back the way the given revision left it, as a new change of its own. A deleted
+/// entry is recreated, unless it was purged. Takes `If-Match` the same way PUT does.
+#[post("/logs/entry/<id>/revert/<revision_id>")]
+pub fn revert_entry(conn: DbConn, auth: WriterAuth, policy: State<EntryPolicy>, if_match: IfMatch, idempotency: IdempotencyKey, id: String, revision_id: String) -> Result<Idempotent<Tagged<Json<PrecipitationLog>>>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+    let parsed_revision_id = parse_id(revision_id.as_str())?;
+
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &(), || {
+        revert(pg_conn, auth.user.id, &policy, &if_match, parsed_id, parsed_revision_id)
+    })
+}
+
+fn revert(conn: &PgConnection, user_id: Uuid, policy: &EntryPolicy, if_match: &IfMatch, id: Uuid, revision_id: Uuid) -> Result<Tagged<Json<PrecipitationLog>>, AppError> {
+    let revision = EntryRevision::read(conn, user_id, id, revision_id)?
+        .ok_or(AppError::NotFound)?;
+    let target = revision.after_entry()?
+        .ok_or_else(|| AppError::invalid("revision_id", "removes the entry, pick the revision before it"))?;
+
+    match PrecipitationLog::read(conn, user_id, id)? {
+        Some(existing) => {
+            if !if_match.allows(&existing.etag()) {
+                return Err(AppError::PreconditionFailed);
+            }
+            let updated = existing.replaced_by(&target);
+            Ok(tagged(save_changes(conn, user_id, policy, &existing, &updated)?))
         }
-    };
+        None if PrecipitationLog::is_in_trash(conn, user_id, id)? => {
+            Err(AppError::Conflict("The entry is in the trash, restore it first".to_string()))
+        }
+        None => {
+            let mut restored = target;
+            restored.modified_at = Utc::now();
+            policy.check(&restored, Utc::now()).map_err(AppError::Validation)?;
 
-    match PrecipitationLog::delete(&conn as &PgConnection, parsed_id) {
-        Ok(_) => Ok(Status::Ok),
-        Err(err) => {
-            error!("{}", err.to_string());
-            Err(Status::InternalServerError)
+            if Station::read(conn, user_id, restored.station_id)?.is_none() {
+                return Err(AppError::invalid("station_id", "must be one of your stations"));
+            }
+
+            let created = PrecipitationLog::recreate(conn, &restored, user_id)?;
+            Ok(tagged(created))
         }
     }
 }
+
+/// Checks and stores a new entry. Its station has to be one of the user's active ones.
+pub(crate) fn create_checked(conn: &PgConnection, user_id: Uuid, policy: &EntryPolicy, new_entry: &PrecipitationLog) -> Result<PrecipitationLog, AppError> {
+    policy.check(new_entry, Utc::now()).map_err(AppError::Validation)?;
+
+    match Station::read(conn, user_id, new_entry.station_id)? {
+        Some(station) if station.active => (),
+        _ => return Err(AppError::invalid("station_id", "must be one of your active stations")),
+    }
+
+    PrecipitationLog::create(conn, new_entry, user_id)
+}
+
+pub(crate) fn read_for_update(conn: &PgConnection, user_id: Uuid, id: Uuid, if_match: &IfMatch) -> Result<PrecipitationLog, AppError> {
+    let existing = PrecipitationLog::read(conn, user_id, id)?.ok_or(AppError::NotFound)?;
+    if !if_match.allows(&existing.etag()) {
+        return Err(AppError::PreconditionFailed);
+    }
+    Ok(existing)
+}
+
+/// Checks `updated` and stores it over `existing`, unless the stored entry changed since
+/// `existing` was read.
+pub(crate) fn save_changes(conn: &PgConnection, user_id: Uuid, policy: &EntryPolicy, existing: &PrecipitationLog, updated: &PrecipitationLog) -> Result<PrecipitationLog, AppError> {
+    policy.check(updated, Utc::now()).map_err(AppError::Validation)?;
+
+    if Station::read(conn, user_id, updated.station_id)?.is_none() {
+        return Err(AppError::invalid("station_id", "must be one of your stations"));
+    }
+
+    PrecipitationLog::update_if_unmodified(conn, updated, existing.modified_at, user_id)
+}
+
+fn tagged(entry: PrecipitationLog) -> Tagged<Json<PrecipitationLog>> {
+    let etag = entry.etag();
+    Tagged(Json(entry), etag)
+}
+
+/// Moves the entry to the trash, where it can be restored until an admin purges it.
+#[delete("/logs/entry/<id>")]
+pub fn delete_entry(conn: DbConn, auth: WriterAuth, idempotency: IdempotencyKey, id: String) -> Result<Idempotent<Status>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &(), || {
+        PrecipitationLog::soft_delete(pg_conn, auth.user.id, parsed_id, auth.user.id)?;
+        Ok(Status::Ok)
+    })
+}
+
+#[get("/logs/trash")]
+pub fn get_trash(conn: DbConn, auth: ReaderAuth) -> Result<Json<Vec<PrecipitationLog>>, AppError> {
+    Ok(Json(PrecipitationLog::read_trash(&conn as &PgConnection, auth.user.id)?))
+}
+
+#[post("/logs/trash/<id>/restore")]
+pub fn restore_entry(conn: DbConn, auth: WriterAuth, idempotency: IdempotencyKey, id: String) -> Result<Idempotent<Tagged<Json<PrecipitationLog>>>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    let pg_conn = &conn as &PgConnection;
+    idempotency.run(pg_conn, auth.user.id, &(), || {
+        let restored = PrecipitationLog::restore(pg_conn, auth.user.id, parsed_id, auth.user.id)?;
+        Ok(tagged(restored))
+    })
+}
+
+/// Removes one entry in the trash for good, whoever it belongs to.
+#[delete("/logs/trash/<id>")]
+pub fn purge_entry(conn: DbConn, admin: AdminAuth, id: String) -> Result<Status, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    PrecipitationLog::purge(&conn as &PgConnection, parsed_id, admin.user.id)?;
+    Ok(Status::Ok)
+}
+
+/// Query string for emptying the trash. `before` is an RFC 3339 timestamp; entries deleted
+/// before it are purged.
+#[derive(FromForm, Clone)]
+pub struct PurgeQuery {
+    pub before: String,
+}
+
+#[derive(Serialize)]
+pub struct PurgeReport {
+    pub purged: usize,
+}
+
+#[delete("/logs/trash?<query..>")]
+pub fn purge_trash(conn: DbConn, admin: AdminAuth, query: Form<PurgeQuery>) -> Result<Json<PurgeReport>, AppError> {
+    let before = parse_timestamp(query.before.as_str()).map_err(AppError::BadRequest)?;
+
+    let purged = PrecipitationLog::purge_deleted_before(&conn as &PgConnection, before, admin.user.id)?;
+    Ok(Json(PurgeReport { purged }))
+}
@@ -1,3 +1,19 @@
+use uuid::Uuid;
+
+use crate::error::AppError;
+
 pub mod user;
 pub mod auth;
 pub mod log;
+pub mod station;
+pub mod import;
+pub mod batch;
+pub mod token;
+pub mod two_factor;
+pub mod password;
+pub mod invite;
+
+/// Parses an id taken from the path, answering a malformed one with a 400.
+pub(crate) fn parse_id(id: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(id).map_err(|err| AppError::BadRequest(format!("Invalid id: {}", err)))
+}
@@ -0,0 +1,83 @@
+use bcrypt::verify;
+use diesel::Connection;
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+
+use crate::DbConn;
+use crate::error::AppError;
+use crate::models::api_token::ApiToken;
+use crate::models::auth::SessionAuth;
+use crate::models::password_reset_token::PasswordResetToken;
+use crate::models::personal_access_token::PersonalAccessToken;
+use crate::models::user::User;
+use crate::utils::password::PasswordPolicy;
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    reset_token: String,
+    new_password: String,
+}
+
+/// Changes the caller's password. Every other session they have is signed out and their personal
+/// access tokens are revoked.
+#[post("/auth/password", data = "<request>")]
+pub fn change_password(conn: DbConn, policy: State<PasswordPolicy>, auth: SessionAuth, request: Json<ChangePasswordRequest>) -> Result<Status, AppError> {
+    if !verify(request.current_password.as_str(), auth.user.password.as_str())? {
+        return Err(AppError::Forbidden);
+    }
+
+    policy.check(request.new_password.as_str(), auth.user.name.as_str())
+        .map_err(|problems| AppError::invalid_field("new_password", problems))?;
+    if request.new_password == request.current_password {
+        return Err(AppError::invalid("new_password", "must differ from the current password"));
+    }
+
+    let pg_conn = &conn as &PgConnection;
+    pg_conn.transaction::<_, AppError, _>(|| {
+        User::set_password(pg_conn, auth.user.id, request.new_password.as_str())?;
+        ApiToken::invalidate_others_for_user(pg_conn, auth.user.id, auth.api_token.id)?;
+        PersonalAccessToken::revoke_all_for_user(pg_conn, auth.user.id)
+    })?;
+
+    Ok(Status::Ok)
+}
+
+/// Sets a new password with a reset token from an admin. Every session the user has is signed
+/// out and their personal access tokens are revoked.
+#[post("/auth/password/reset", data = "<request>")]
+pub fn reset_password(conn: DbConn, policy: State<PasswordPolicy>, request: Json<ResetPasswordRequest>) -> Result<Status, AppError> {
+    let pg_conn = &conn as &PgConnection;
+
+    let token = match PasswordResetToken::find_valid(pg_conn, request.reset_token.as_str())? {
+        Some(t) => t,
+        None => return Err(AppError::Unauthorized),
+    };
+    let user = match User::read(pg_conn, token.user_id) {
+        Ok(u) => u,
+        Err(AppError::NotFound) => return Err(AppError::Unauthorized),
+        Err(err) => return Err(err),
+    };
+
+    // Checked before the token is used up so a rejected password can be retried.
+    policy.check(request.new_password.as_str(), user.name.as_str())
+        .map_err(|problems| AppError::invalid_field("new_password", problems))?;
+
+    pg_conn.transaction::<_, AppError, _>(|| {
+        if !PasswordResetToken::mark_used(pg_conn, token.id)? {
+            return Err(AppError::Unauthorized);
+        }
+        User::set_password(pg_conn, user.id, request.new_password.as_str())?;
+        ApiToken::invalidate_all_for_user(pg_conn, &user)?;
+        PersonalAccessToken::revoke_all_for_user(pg_conn, user.id)
+    })?;
+
+    Ok(Status::Ok)
+}
@@ -0,0 +1,110 @@
+use chrono::Utc;
+use chrono_tz::Tz;
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+use crate::DbConn;
+use crate::error::{AppError, FieldError};
+use crate::models::auth::{ReaderAuth, WriterAuth};
+use crate::models::station::Station;
+use crate::routes::parse_id;
+
+#[derive(Deserialize, Clone)]
+pub struct StationRequest {
+    pub name: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub elevation: Option<f32>,
+    pub time_zone: Option<String>,
+    pub active: Option<bool>,
+}
+
+impl StationRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        if self.name.trim().is_empty() {
+            errors.push(FieldError::new("name", "must not be empty"));
+        }
+        if !self.latitude.map_or(true, |l| l >= -90.0 && l <= 90.0) {
+            errors.push(FieldError::new("latitude", "must be between -90 and 90"));
+        }
+        if !self.longitude.map_or(true, |l| l >= -180.0 && l <= 180.0) {
+            errors.push(FieldError::new("longitude", "must be between -180 and 180"));
+        }
+        if !self.time_zone.as_ref().map_or(true, |tz| tz.parse::<Tz>().is_ok()) {
+            errors.push(FieldError::new("time_zone", "must be an IANA time zone name"));
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(AppError::Validation(errors)),
+        }
+    }
+
+    fn time_zone(&self) -> String {
+        self.time_zone.clone().unwrap_or_else(|| "UTC".to_string())
+    }
+}
+
+#[get("/stations")]
+pub fn get_all_stations(conn: DbConn, auth: ReaderAuth) -> Result<Json<Vec<Station>>, AppError> {
+    Ok(Json(Station::read_all(&conn as &PgConnection, auth.user.id)?))
+}
+
+#[get("/stations/<id>")]
+pub fn get_station(conn: DbConn, auth: ReaderAuth, id: String) -> Result<Json<Station>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    match Station::read(&conn as &PgConnection, auth.user.id, parsed_id)? {
+        Some(station) => Ok(Json(station)),
+        None => Err(AppError::NotFound),
+    }
+}
+
+#[post("/stations", data = "<station>")]
+pub fn create_station(conn: DbConn, auth: WriterAuth, station: Json<StationRequest>) -> Result<Json<Station>, AppError> {
+    station.validate()?;
+
+    let new_station = Station::new(
+        auth.user.id,
+        station.name.trim().to_string(),
+        station.latitude,
+        station.longitude,
+        station.elevation,
+        station.time_zone(),
+        station.active.unwrap_or(true),
+    );
+
+    Ok(Json(Station::create(&conn as &PgConnection, &new_station)?))
+}
+
+#[put("/stations/<id>", data = "<station>")]
+pub fn update_station(conn: DbConn, auth: WriterAuth, id: String, station: Json<StationRequest>) -> Result<Json<Station>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+    station.validate()?;
+
+    let mut db_station = match Station::read(&conn as &PgConnection, auth.user.id, parsed_id)? {
+        Some(s) => s,
+        None => return Err(AppError::NotFound),
+    };
+
+    db_station.name = station.name.trim().to_string();
+    db_station.latitude = station.latitude;
+    db_station.longitude = station.longitude;
+    db_station.elevation = station.elevation;
+    db_station.time_zone = station.time_zone();
+    db_station.active = station.active.unwrap_or(db_station.active);
+    db_station.modified_at = Utc::now();
+
+    Ok(Json(Station::update(&conn as &PgConnection, &db_station)?))
+}
+
+#[delete("/stations/<id>")]
+pub fn delete_station(conn: DbConn, auth: WriterAuth, id: String) -> Result<Status, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    // Stations that still have entries can't be removed; mark them inactive instead.
+    Station::delete(&conn as &PgConnection, auth.user.id, parsed_id)?;
+    Ok(Status::Ok)
+}
@@ -0,0 +1,59 @@
+use chrono::Duration;
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+use crate::DbConn;
+use crate::error::AppError;
+use crate::models::auth::SessionAuth;
+use crate::models::personal_access_token::{PersonalAccessToken, Scope};
+use crate::routes::parse_id;
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    name: String,
+    scopes: Vec<Scope>,
+    expires_in_days: Option<i64>,
+}
+
+/// The new token along with its secret. This is the only time the secret is returned.
+#[derive(Serialize)]
+pub struct CreateTokenResponse {
+    token: PersonalAccessToken,
+    secret: String,
+}
+
+#[get("/auth/tokens")]
+pub fn get_tokens(conn: DbConn, auth: SessionAuth) -> Result<Json<Vec<PersonalAccessToken>>, AppError> {
+    Ok(Json(PersonalAccessToken::read_all(&conn as &PgConnection, auth.user.id)?))
+}
+
+/// Creates a personal access token. Tokens can only be created from a login session, and only
+/// with scopes the user's role allows.
+#[post("/auth/tokens", data = "<request>")]
+pub fn create_token(conn: DbConn, auth: SessionAuth, request: Json<CreateTokenRequest>) -> Result<Json<CreateTokenResponse>, AppError> {
+    let name = request.name.trim();
+    let role = auth.user.role();
+    if name.is_empty() {
+        return Err(AppError::invalid("name", "must not be empty"));
+    }
+    if request.scopes.is_empty() || !request.scopes.iter().all(|s| s.allowed_for(role)) {
+        return Err(AppError::invalid("scopes", "must be a non-empty list of scopes your role allows"));
+    }
+    if request.expires_in_days.map_or(false, |d| d <= 0) {
+        return Err(AppError::invalid("expires_in_days", "must be positive"));
+    }
+
+    let lifetime = request.expires_in_days.map(Duration::days);
+    let (token, secret) = PersonalAccessToken::create(&conn as &PgConnection, auth.user.id, name.to_string(), &request.scopes, lifetime)?;
+
+    Ok(Json(CreateTokenResponse { token, secret }))
+}
+
+#[delete("/auth/tokens/<id>")]
+pub fn revoke_token(conn: DbConn, auth: SessionAuth, id: String) -> Result<Status, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    PersonalAccessToken::delete(&conn as &PgConnection, auth.user.id, parsed_id)?;
+    Ok(Status::Ok)
+}
@@ -0,0 +1,133 @@
+use bcrypt::verify;
+use diesel::Connection;
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+use crate::DbConn;
+use crate::error::AppError;
+use crate::models::auth::SessionAuth;
+use crate::models::recovery_code::RecoveryCode;
+use crate::models::user::User;
+use crate::utils::totp;
+
+const ISSUER: &str = "Rain Logger";
+
+#[derive(Serialize)]
+pub struct TwoFactorStatus {
+    enabled: bool,
+    pending: bool,
+    recovery_codes_remaining: i64,
+}
+
+/// The new secret, both raw for typing in and as an `otpauth://` URI for a QR code.
+#[derive(Serialize)]
+pub struct TwoFactorSetup {
+    secret: String,
+    provisioning_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorCodeRequest {
+    code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisableTwoFactorRequest {
+    password: String,
+    code: String,
+}
+
+/// Recovery codes are only ever returned here, when they're generated.
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+#[get("/auth/2fa")]
+pub fn get_two_factor(conn: DbConn, auth: SessionAuth) -> Result<Json<TwoFactorStatus>, AppError> {
+    let remaining = RecoveryCode::remaining(&conn as &PgConnection, auth.user.id)?;
+
+    Ok(Json(TwoFactorStatus {
+        enabled: auth.user.totp_enabled,
+        pending: !auth.user.totp_enabled && auth.user.totp_secret.is_some(),
+        recovery_codes_remaining: remaining,
+    }))
+}
+
+/// Starts enrollment with a fresh secret. Nothing changes at login until it's confirmed with
+/// `/auth/2fa/enable`.
+#[post("/auth/2fa/setup")]
+pub fn setup_two_factor(conn: DbConn, auth: SessionAuth) -> Result<Json<TwoFactorSetup>, AppError> {
+    if auth.user.totp_enabled {
+        return Err(AppError::Conflict("Two-factor is already enabled".to_string()));
+    }
+
+    let secret = totp::generate_secret();
+    User::start_totp_enrollment(&conn as &PgConnection, auth.user.id, secret.as_str())?;
+
+    Ok(Json(TwoFactorSetup {
+        provisioning_uri: totp::provisioning_uri(secret.as_str(), ISSUER, auth.user.name.as_str()),
+        secret,
+    }))
+}
+
+/// Confirms enrollment with a code from the authenticator and hands out recovery codes.
+#[post("/auth/2fa/enable", data = "<request>")]
+pub fn enable_two_factor(conn: DbConn, auth: SessionAuth, request: Json<TwoFactorCodeRequest>) -> Result<Json<RecoveryCodes>, AppError> {
+    if auth.user.totp_enabled {
+        return Err(AppError::Conflict("Two-factor is already enabled".to_string()));
+    }
+    if auth.user.totp_secret.is_none() {
+        return Err(AppError::Conflict("Two-factor setup hasn't been started".to_string()));
+    }
+
+    let step = match auth.user.check_totp(request.code.as_str()) {
+        Some(s) => s,
+        None => return Err(AppError::invalid("code", "doesn't match")),
+    };
+
+    let pg_conn = &conn as &PgConnection;
+    let recovery_codes = pg_conn.transaction::<_, AppError, _>(|| {
+        User::enable_totp(pg_conn, auth.user.id, step)?;
+        RecoveryCode::replace_for_user(pg_conn, auth.user.id)
+    })?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Replaces the user's recovery codes. Needs a current code so a stolen session alone can't.
+#[post("/auth/2fa/recovery-codes", data = "<request>")]
+pub fn regenerate_recovery_codes(conn: DbConn, auth: SessionAuth, request: Json<TwoFactorCodeRequest>) -> Result<Json<RecoveryCodes>, AppError> {
+    if !auth.user.totp_enabled {
+        return Err(AppError::Conflict("Two-factor isn't enabled".to_string()));
+    }
+
+    let pg_conn = &conn as &PgConnection;
+    if !auth.user.check_second_factor(pg_conn, request.code.as_str())? {
+        return Err(AppError::Forbidden);
+    }
+
+    let recovery_codes = RecoveryCode::replace_for_user(pg_conn, auth.user.id)?;
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Turns two-factor off. Needs both the password and a current or recovery code.
+#[post("/auth/2fa/disable", data = "<request>")]
+pub fn disable_two_factor(conn: DbConn, auth: SessionAuth, request: Json<DisableTwoFactorRequest>) -> Result<Status, AppError> {
+    if !auth.user.totp_enabled {
+        return Err(AppError::Conflict("Two-factor isn't enabled".to_string()));
+    }
+
+    if !verify(request.password.as_str(), auth.user.password.as_str())? {
+        return Err(AppError::Forbidden);
+    }
+
+    let pg_conn = &conn as &PgConnection;
+    if !auth.user.check_second_factor(pg_conn, request.code.as_str())? {
+        return Err(AppError::Forbidden);
+    }
+
+    User::disable_totp(pg_conn, auth.user.id)?;
+    Ok(Status::Ok)
+}
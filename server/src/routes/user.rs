@@ -1,23 +1,133 @@
-use std::error::Error;
-
+use chrono::{DateTime, Duration, Utc};
 use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::State;
 use rocket_contrib::json::Json;
-use uuid::Uuid;
 
 use crate::DbConn;
-use crate::models::user::{User, UserJson};
-use crate::models::auth::Auth;
+use crate::error::AppError;
+use crate::models::api_token::ApiToken;
+use crate::models::password_reset_token::PasswordResetToken;
+use crate::models::user::{Role, User, UserJson, UserUpdateSet};
+use crate::models::auth::{AdminAuth, Auth};
+use crate::routes::parse_id;
+use crate::utils::password::PasswordPolicy;
+
+const PASSWORD_RESET_HOURS: i64 = 24;
 
 #[get("/users")]
-pub fn get_all_users(conn: DbConn, _auth: &Auth) -> Result<Json<Vec<UserJson>>, Box<dyn Error>> {
+pub fn get_all_users(conn: DbConn, _admin: AdminAuth) -> Result<Json<Vec<UserJson>>, AppError> {
     let dirty_result = User::read_all(&conn as &PgConnection)?;
     let clean_result: Vec<UserJson> = dirty_result.into_iter().map(|u| UserJson::transform(&u)).collect();
     Ok(Json(clean_result))
 }
 
 #[get("/users/<id>")]
-pub fn get_user(conn: DbConn, _auth: &Auth, id: String) -> Result<Json<UserJson>, Box<dyn Error>> {
-    let dirty_result = User::read(&conn as &PgConnection, Uuid::parse_str(&id)?)?;
-    let clean_result: Vec<UserJson> = dirty_result.into_iter().map(|u| UserJson::transform(&u)).collect();
-    Ok(Json(clean_result[0].clone()))
+pub fn get_user(conn: DbConn, auth: &Auth, id: String) -> Result<Json<UserJson>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    // Anyone may look themselves up; everyone else is an admin's business.
+    if parsed_id != auth.user.id && auth.user.role() != Role::Admin {
+        return Err(AppError::Forbidden);
+    }
+
+    let user = User::read(&conn as &PgConnection, parsed_id)?;
+    Ok(Json(UserJson::transform(&user)))
+}
+
+#[derive(Deserialize)]
+pub struct CreateUserRequest {
+    pub name: String,
+    pub password: String,
+    pub role: Option<Role>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserRequest {
+    pub name: Option<String>,
+    pub enabled: Option<bool>,
+    pub role: Option<Role>,
+}
+
+/// The reset token to pass on to the user. It's only shown here.
+#[derive(Serialize)]
+pub struct PasswordResetResponse {
+    pub reset_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[post("/users", data = "<user>")]
+pub fn create_user(conn: DbConn, _admin: AdminAuth, policy: State<PasswordPolicy>, user: Json<CreateUserRequest>) -> Result<Json<UserJson>, AppError> {
+    if user.name.trim().is_empty() {
+        return Err(AppError::invalid("name", "must not be empty"));
+    }
+    policy.check(user.password.as_str(), user.name.trim())
+        .map_err(|problems| AppError::invalid_field("password", problems))?;
+
+    let new_user = User::new(user.name.trim().to_string(), user.password.as_str(), user.role.unwrap_or(Role::Observer))?;
+    let created = User::create(&conn as &PgConnection, new_user)?;
+
+    Ok(Json(UserJson::transform(&created)))
+}
+
+#[put("/users/<id>", data = "<update>")]
+pub fn update_user(conn: DbConn, admin: AdminAuth, id: String, update: Json<UpdateUserRequest>) -> Result<Json<UserJson>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    // Admins can't lock themselves out; another admin has to do it.
+    if parsed_id == admin.user.id {
+        if update.enabled == Some(false) {
+            return Err(AppError::invalid("enabled", "you can't disable yourself"));
+        }
+        if update.role.map_or(false, |r| r != Role::Admin) {
+            return Err(AppError::invalid("role", "you can't demote yourself"));
+        }
+    }
+
+    let name = update.name.as_ref().map(|n| n.trim().to_string());
+    if name.as_ref().map_or(false, |n| n.is_empty()) {
+        return Err(AppError::invalid("name", "must not be empty"));
+    }
+
+    let update_set = UserUpdateSet::new(name, None, update.enabled, update.role);
+    User::update(&conn as &PgConnection, parsed_id, update_set)?;
+    let user = User::read(&conn as &PgConnection, parsed_id)?;
+
+    // A disabled user shouldn't keep any sessions they had open.
+    if !user.enabled {
+        ApiToken::invalidate_all_for_user(&conn as &PgConnection, &user)?;
+    }
+
+    Ok(Json(UserJson::transform(&user)))
+}
+
+#[delete("/users/<id>")]
+pub fn delete_user(conn: DbConn, admin: AdminAuth, id: String) -> Result<Status, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    if parsed_id == admin.user.id {
+        return Err(AppError::invalid("id", "you can't delete yourself"));
+    }
+
+    User::delete(&conn as &PgConnection, parsed_id)?;
+    Ok(Status::Ok)
+}
+
+/// Issues a one-time token the user can set a new password with at `/auth/password/reset`.
+/// Their current password keeps working until then.
+#[post("/users/<id>/password-reset")]
+pub fn reset_user_password(conn: DbConn, admin: AdminAuth, id: String) -> Result<Json<PasswordResetResponse>, AppError> {
+    let parsed_id = parse_id(id.as_str())?;
+
+    let (token, secret) = match PasswordResetToken::issue(&conn as &PgConnection, parsed_id, admin.user.id, Duration::hours(PASSWORD_RESET_HOURS)) {
+        Ok(issued) => issued,
+        // The only thing the insert can conflict with is the user not existing.
+        Err(AppError::Conflict(_)) => return Err(AppError::NotFound),
+        Err(err) => return Err(err),
+    };
+
+    Ok(Json(PasswordResetResponse {
+        reset_token: secret,
+        expires_at: token.expires_at,
+    }))
 }
@@ -5,6 +5,85 @@ table! {
         created_at -> Timestamptz,
         modified_at -> Timestamptz,
         user_id -> Uuid,
+        user_agent -> Nullable<Varchar>,
+        ip_address -> Nullable<Varchar>,
+        device_name -> Nullable<Varchar>,
+        last_used_at -> Timestamptz,
+    }
+}
+
+table! {
+    entry_revisions (id) {
+        id -> Uuid,
+        entry_id -> Uuid,
+        user_id -> Uuid,
+        changed_by -> Nullable<Uuid>,
+        action -> Varchar,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    idempotent_requests (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        key -> Varchar,
+        request_hash -> Varchar,
+        status -> Nullable<Int4>,
+        body -> Nullable<Text>,
+        etag -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    invites (id) {
+        id -> Uuid,
+        code_hash -> Varchar,
+        role -> Varchar,
+        max_uses -> Int4,
+        uses -> Int4,
+        expires_at -> Timestamptz,
+        created_by -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    login_attempts (id) {
+        id -> Uuid,
+        user_name -> Varchar,
+        user_id -> Nullable<Uuid>,
+        ip_address -> Nullable<Varchar>,
+        succeeded -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    password_reset_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_by -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    personal_access_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Varchar,
+        token_hash -> Varchar,
+        scopes -> Array<Text>,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -19,6 +98,44 @@ table! {
         deleted -> Bool,
         created_at -> Timestamptz,
         modified_at -> Timestamptz,
+        user_id -> Uuid,
+        station_id -> Uuid,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Uuid,
+        api_token_id -> Uuid,
+        token_hash -> Varchar,
+        used -> Bool,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    stations (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Varchar,
+        latitude -> Nullable<Float8>,
+        longitude -> Nullable<Float8>,
+        elevation -> Nullable<Float4>,
+        time_zone -> Varchar,
+        active -> Bool,
+        created_at -> Timestamptz,
+        modified_at -> Timestamptz,
     }
 }
 
@@ -30,13 +147,37 @@ table! {
         enabled -> Bool,
         created_at -> Timestamptz,
         modified_at -> Timestamptz,
+        role -> Varchar,
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled -> Bool,
+        totp_last_step -> Int8,
     }
 }
 
 joinable!(api_tokens -> users (user_id));
+joinable!(entry_revisions -> users (user_id));
+joinable!(idempotent_requests -> users (user_id));
+joinable!(invites -> users (created_by));
+joinable!(login_attempts -> users (user_id));
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(personal_access_tokens -> users (user_id));
+joinable!(precipitation_logs -> stations (station_id));
+joinable!(precipitation_logs -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(refresh_tokens -> api_tokens (api_token_id));
+joinable!(stations -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
     api_tokens,
+    entry_revisions,
+    idempotent_requests,
+    invites,
+    login_attempts,
+    password_reset_tokens,
+    personal_access_tokens,
     precipitation_logs,
+    recovery_codes,
+    refresh_tokens,
+    stations,
     users,
 );
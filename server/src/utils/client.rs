@@ -0,0 +1,81 @@
+use std::env;
+use std::net::IpAddr;
+
+use rocket::{Outcome, State};
+use rocket::request::{self, FromRequest, Request};
+
+/// Proxies trusted to report the client's address in `X-Real-IP`, read from the comma separated
+/// `TRUSTED_PROXIES`. With none configured the header is ignored, since clients can send it too.
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn from_env() -> Self {
+        TrustedProxies::parse(&env::var("TRUSTED_PROXIES").unwrap_or_default())
+    }
+
+    pub fn parse(value: &str) -> Self {
+        TrustedProxies(value.split(',').filter_map(|p| p.trim().parse().ok()).collect())
+    }
+
+    /// The client's address for a request from `peer` carrying `real_ip` in `X-Real-IP`.
+    pub fn client_ip(&self, peer: Option<IpAddr>, real_ip: Option<&str>) -> Option<IpAddr> {
+        match peer {
+            Some(ip) if self.0.contains(&ip) => real_ip.and_then(|v| v.trim().parse().ok()).or(peer),
+            _ => peer,
+        }
+    }
+}
+
+/// The address a request came from. `X-Real-IP` is only honoured when the request comes through
+/// one of the `TrustedProxies`. Never fails; the address is simply `None` when Rocket can't tell.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let peer = request.remote().map(|address| address.ip());
+        let client_ip = match request.guard::<State<TrustedProxies>>() {
+            Outcome::Success(proxies) => proxies.client_ip(peer, request.headers().get_one("X-Real-IP")),
+            _ => peer,
+        };
+
+        Outcome::Success(ClientIp(client_ip))
+    }
+}
+
+/// The request's `User-Agent` header, if it sent exactly one.
+pub struct UserAgent(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let values: Vec<_> = request.headers().get("User-Agent").collect();
+        let user_agent = match values.as_slice() {
+            [value] => Some(value.to_string()),
+            _ => None,
+        };
+
+        Outcome::Success(UserAgent(user_agent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn honours_real_ip_only_from_trusted_proxies() {
+        let proxies = TrustedProxies::parse("10.0.0.1, ::1, nonsense");
+        assert_eq!(2, proxies.0.len());
+
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(Some(client), proxies.client_ip(Some(proxy), Some("203.0.113.7")));
+        assert_eq!(Some(proxy), proxies.client_ip(Some(proxy), Some("garbage")));
+        assert_eq!(Some(proxy), proxies.client_ip(Some(proxy), None));
+        assert_eq!(Some(client), proxies.client_ip(Some(client), Some("198.51.100.1")));
+        assert_eq!(None, TrustedProxies::parse("").client_ip(None, Some("198.51.100.1")));
+    }
+}
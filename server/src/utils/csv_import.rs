@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use csv::{Position, ReaderBuilder, StringRecord};
+use uuid::Uuid;
+
+use crate::models::precipitation_log::{PrecipitationLog, PrecipitationType};
+use crate::models::station::Station;
+use crate::utils::validation::EntryPolicy;
+
+/// Names of the CSV header columns each entry field is read from.
+#[derive(Clone, Debug)]
+pub struct ColumnMapping {
+    pub measurement: String,
+    pub logged_at: String,
+    pub ptype: String,
+    pub notes: String,
+    pub anomaly: String,
+    pub station: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            measurement: "measurement".to_string(),
+            logged_at: "logged_at".to_string(),
+            ptype: "ptype".to_string(),
+            notes: "notes".to_string(),
+            anomaly: "anomaly".to_string(),
+            station: "station".to_string(),
+        }
+    }
+}
+
+pub struct ImportOptions {
+    pub columns: ColumnMapping,
+    pub delimiter: u8,
+    /// A chrono format string for `logged_at`. Without one the column must be RFC 3339.
+    pub date_format: Option<String>,
+    /// Zone for timestamps without an offset when the station doesn't say otherwise.
+    pub time_zone: Option<Tz>,
+    /// Station used for rows that leave the station column empty or don't have one.
+    pub default_station: Option<Uuid>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            columns: ColumnMapping::default(),
+            delimiter: b',',
+            date_format: None,
+            time_zone: None,
+            default_station: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RowError {
+    /// 1-based line in the uploaded file, counting the header.
+    pub line: usize,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+impl RowError {
+    fn new(line: usize, column: Option<&str>, message: String) -> Self {
+        Self {
+            line,
+            column: column.map(|c| c.to_string()),
+            message,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ParsedImport {
+    pub rows: usize,
+    pub entries: Vec<PrecipitationLog>,
+    pub errors: Vec<RowError>,
+}
+
+struct ColumnIndexes {
+    measurement: usize,
+    logged_at: usize,
+    ptype: Option<usize>,
+    notes: Option<usize>,
+    anomaly: Option<usize>,
+    station: Option<usize>,
+}
+
+/// Parses a CSV upload into entries owned by `user_id`. Stations may be referenced by id or by
+/// name and must be active ones among `stations`. Every row is checked, including against
+/// `policy`; rows that fail are reported in `errors` instead of `entries`.
+pub fn parse_entries(data: &str, options: &ImportOptions, policy: &EntryPolicy, user_id: Uuid, stations: &[Station]) -> ParsedImport {
+    let mut result = ParsedImport::default();
+    let mut reader = ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data.as_bytes());
+
+    let headers = match reader.headers() {
+        Ok(h) => h.clone(),
+        Err(err) => {
+            result.errors.push(RowError::new(1, None, err.to_string()));
+            return result;
+        }
+    };
+
+    let indexes = match column_indexes(&headers, &options.columns) {
+        Ok(i) => i,
+        Err(err) => {
+            result.errors.push(err);
+            return result;
+        }
+    };
+
+    let mut stations_by_key: HashMap<String, &Station> = HashMap::new();
+    for station in stations {
+        stations_by_key.insert(station.id.to_string(), station);
+        stations_by_key.insert(station.name.to_lowercase(), station);
+    }
+
+    for (index, record) in reader.records().enumerate() {
+        result.rows += 1;
+        let position = match record {
+            Ok(ref r) => r.position(),
+            Err(ref err) => err.position(),
+        };
+        let line = position.map_or(index + 2, |p| record_line(data, p));
+
+        let record = match record {
+            Ok(r) => r,
+            Err(err) => {
+                result.errors.push(RowError::new(line, None, err.to_string()));
+                continue;
+            }
+        };
+
+        match parse_record(&record, line, &indexes, options, policy, user_id, &stations_by_key) {
+            Ok(entry) => result.entries.push(entry),
+            Err(mut errors) => result.errors.append(&mut errors),
+        }
+    }
+
+    result
+}
+
+/// The line a record starts on. Quoted line breaks and skipped blank lines mean it can't be told
+/// from the record's index, and the reader's position is from before the blank lines it skipped.
+fn record_line(data: &str, position: &Position) -> usize {
+    let start = position.byte() as usize;
+    let skipped = data.get(start..).map_or(0, |rest| {
+        rest.chars()
+            .take_while(|c| *c == '\n' || *c == '\r')
+            .filter(|c| *c == '\n')
+            .count()
+    });
+
+    position.line() as usize + skipped
+}
+
+fn column_indexes(headers: &StringRecord, columns: &ColumnMapping) -> Result<ColumnIndexes, RowError> {
+    let find = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let required = |name: &str| find(name)
+        .ok_or_else(|| RowError::new(1, Some(name), format!("Missing required column {}", name)));
+
+    Ok(ColumnIndexes {
+        measurement: required(&columns.measurement)?,
+        logged_at: required(&columns.logged_at)?,
+        ptype: find(&columns.ptype),
+        notes: find(&columns.notes),
+        anomaly: find(&columns.anomaly),
+        station: find(&columns.station),
+    })
+}
+
+fn parse_record(
+    record: &StringRecord,
+    line: usize,
+    indexes: &ColumnIndexes,
+    options: &ImportOptions,
+    policy: &EntryPolicy,
+    user_id: Uuid,
+    stations: &HashMap<String, &Station>,
+) -> Result<PrecipitationLog, Vec<RowError>> {
+    let columns = &options.columns;
+    let field = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or("");
+    let mut errors = Vec::new();
+
+    let station = match field(indexes.station) {
+        "" => options.default_station.and_then(|id| stations.get(&id.to_string()).cloned()),
+        value => stations.get(&value.to_lowercase()).cloned(),
+    };
+    match station {
+        None => errors.push(RowError::new(line, Some(&columns.station), "Unknown or missing station".to_string())),
+        Some(s) if !s.active => errors.push(RowError::new(line, Some(&columns.station), "Station isn't active".to_string())),
+        Some(_) => (),
+    }
+
+    let measurement = match field(Some(indexes.measurement)).parse::<f32>() {
+        Ok(m) if m.is_finite() => Some(m),
+        _ => {
+            errors.push(RowError::new(line, Some(&columns.measurement), "Measurement must be a number".to_string()));
+            None
+        }
+    };
+
+    let time_zone = station
+        .and_then(|s| s.time_zone.parse::<Tz>().ok())
+        .or(options.time_zone)
+        .unwrap_or(chrono_tz::UTC);
+    let logged_at = match parse_logged_at(field(Some(indexes.logged_at)), options.date_format.as_deref(), time_zone) {
+        Some(t) => Some(t),
+        None => {
+            errors.push(RowError::new(line, Some(&columns.logged_at), "Unrecognized timestamp".to_string()));
+            None
+        }
+    };
+
+    let ptype = match parse_ptype(field(indexes.ptype)) {
+        Some(p) => Some(p),
+        None => {
+            errors.push(RowError::new(line, Some(&columns.ptype), "Unknown precipitation type".to_string()));
+            None
+        }
+    };
+
+    let anomaly = match parse_bool(field(indexes.anomaly)) {
+        Some(a) => Some(a),
+        None => {
+            errors.push(RowError::new(line, Some(&columns.anomaly), "Anomaly must be true or false".to_string()));
+            None
+        }
+    };
+
+    let notes = match field(indexes.notes) {
+        "" => None,
+        n => Some(n.to_string()),
+    };
+
+    match (station, measurement, logged_at, ptype, anomaly) {
+        (Some(station), Some(measurement), Some(logged_at), Some(ptype), Some(anomaly)) if errors.is_empty() => {
+            let entry = PrecipitationLog::new(user_id, station.id, measurement, logged_at, ptype, notes, anomaly);
+            match policy.check(&entry, Utc::now()) {
+                Ok(_) => Ok(entry),
+                Err(problems) => Err(problems
+                    .into_iter()
+                    .map(|p| RowError::new(line, Some(column_for(columns, &p.field)), format!("{} {}", p.field, p.message)))
+                    .collect()),
+            }
+        }
+        _ => Err(errors),
+    }
+}
+
+// The CSV column an entry field was read from.
+fn column_for<'a>(columns: &'a ColumnMapping, field: &str) -> &'a str {
+    match field {
+        "measurement" => &columns.measurement,
+        "logged_at" => &columns.logged_at,
+        "ptype" => &columns.ptype,
+        "notes" => &columns.notes,
+        "anomaly" => &columns.anomaly,
+        _ => &columns.station,
+    }
+}
+
+fn parse_logged_at(value: &str, format: Option<&str>, time_zone: Tz) -> Option<DateTime<Utc>> {
+    let local = match format {
+        None => return DateTime::parse_from_rfc3339(value).ok().map(|t| t.with_timezone(&Utc)),
+        Some(format) => NaiveDateTime::parse_from_str(value, format)
+            .or_else(|_| NaiveDate::parse_from_str(value, format).map(|d| d.and_hms(0, 0, 0)))
+            .ok()?,
+    };
+
+    time_zone.from_local_datetime(&local).earliest().map(|t| t.with_timezone(&Utc))
+}
+
+// The old numeric codes are still accepted, as older spreadsheets use them.
+fn parse_ptype(value: &str) -> Option<PrecipitationType> {
+    match value.to_lowercase().as_str() {
+        "" => Some(PrecipitationType::Unidentified),
+        v => PrecipitationType::parse(v).or_else(|| v.parse().ok().and_then(PrecipitationType::from_i16)),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "" | "false" | "f" | "no" | "n" | "0" => Some(false),
+        "true" | "t" | "yes" | "y" | "1" => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(name: &str, time_zone: &str) -> Station {
+        Station::new(Uuid::nil(), name.to_string(), None, None, None, time_zone.to_string(), true)
+    }
+
+    #[test]
+    fn parses_rows_with_default_columns() {
+        let stations = vec![station("Back yard", "UTC")];
+        let data = "station,logged_at,measurement,ptype,anomaly,notes\n\
+                    Back yard,2020-07-04T12:00:00Z,1.5,liquid,false,Afternoon storm\n\
+                    back yard,2020-07-05T12:00:00Z,0.25,1,,\n";
+        let result = parse_entries(data, &ImportOptions::default(), &EntryPolicy::default(), Uuid::nil(), &stations);
+        assert_eq!(2, result.rows);
+        assert!(result.errors.is_empty());
+        assert_eq!(1.5, result.entries[0].measurement);
+        assert_eq!(Some("Afternoon storm".to_string()), result.entries[0].notes);
+        assert_eq!(None, result.entries[1].notes);
+        assert_eq!(stations[0].id, result.entries[1].station_id);
+    }
+
+    #[test]
+    fn applies_column_mapping_and_station_time_zone() {
+        let stations = vec![station("Gauge", "America/Denver")];
+        let options = ImportOptions {
+            columns: ColumnMapping {
+                measurement: "Rain (mm)".to_string(),
+                logged_at: "Date".to_string(),
+                ..ColumnMapping::default()
+            },
+            date_format: Some("%m/%d/%Y".to_string()),
+            default_station: Some(stations[0].id),
+            ..ImportOptions::default()
+        };
+        let data = "Date,Rain (mm)\n07/04/2020,3.0\n";
+        let result = parse_entries(data, &options, &EntryPolicy::default(), Uuid::nil(), &stations);
+        assert!(result.errors.is_empty());
+        assert_eq!(Utc.ymd(2020, 7, 4).and_hms(6, 0, 0), result.entries[0].logged_at);
+    }
+
+    #[test]
+    fn reports_every_bad_field() {
+        let stations = vec![station("Gauge", "UTC")];
+        let data = "station,logged_at,measurement,ptype\nNowhere,yesterday,lots,hail\n";
+        let result = parse_entries(data, &ImportOptions::default(), &EntryPolicy::default(), Uuid::nil(), &stations);
+        assert_eq!(1, result.rows);
+        assert!(result.entries.is_empty());
+        let columns: Vec<_> = result.errors.iter().map(|e| e.column.clone().unwrap()).collect();
+        assert_eq!(vec!["station", "measurement", "logged_at", "ptype"], columns);
+        assert!(result.errors.iter().all(|e| e.line == 2));
+    }
+
+    #[test]
+    fn rejects_inactive_stations() {
+        let mut retired = station("Old gauge", "UTC");
+        retired.active = false;
+        let stations = vec![retired];
+        let data = "station,logged_at,measurement,ptype\nOld gauge,2020-07-04T12:00:00Z,1.0,liquid\n";
+        let result = parse_entries(data, &ImportOptions::default(), &EntryPolicy::default(), Uuid::nil(), &stations);
+        assert!(result.entries.is_empty());
+        assert_eq!(1, result.errors.len());
+        assert_eq!(Some("station".to_string()), result.errors[0].column);
+    }
+
+    #[test]
+    fn reports_lines_as_they_are_in_the_file() {
+        let stations = vec![station("Gauge", "UTC")];
+        let data = "station,logged_at,measurement,notes
+Gauge,2020-07-04T12:00:00Z,1.0,\"Heavy rain,
+then hail\"
+
+Gauge,2020-07-04T13:00:00Z,lots,
+";
+        let result = parse_entries(data, &ImportOptions::default(), &EntryPolicy::default(), Uuid::nil(), &stations);
+        assert_eq!(2, result.rows);
+        assert_eq!(Some("Heavy rain,\nthen hail".to_string()), result.entries[0].notes);
+        assert_eq!(1, result.errors.len());
+        assert_eq!(5, result.errors[0].line);
+    }
+
+    #[test]
+    fn applies_entry_policy() {
+        let stations = vec![station("Gauge", "UTC")];
+        let data = "station,logged_at,measurement
+Gauge,2020-07-04T12:00:00Z,-1
+";
+        let result = parse_entries(data, &ImportOptions::default(), &EntryPolicy::default(), Uuid::nil(), &stations);
+        assert!(result.entries.is_empty());
+        assert_eq!(Some("measurement".to_string()), result.errors[0].column);
+    }
+
+    #[test]
+    fn rejects_missing_required_columns() {
+        let result = parse_entries("station,notes\nGauge,hi\n", &ImportOptions::default(), &EntryPolicy::default(), Uuid::nil(), &[]);
+        assert_eq!(0, result.rows);
+        assert_eq!(Some("measurement".to_string()), result.errors[0].column);
+    }
+}
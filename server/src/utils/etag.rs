@@ -0,0 +1,56 @@
+use rocket::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+
+/// The request's `If-Match` header, if it sent one.
+pub struct IfMatch(pub Option<String>);
+
+impl IfMatch {
+    /// Whether a change may go ahead on something whose current tag is `etag`. Without the header
+    /// anything goes; otherwise one of the listed tags, or `*`, has to match. Weak tags never do.
+    pub fn allows(&self, etag: &str) -> bool {
+        match self.0 {
+            None => true,
+            Some(ref value) => value.split(',').map(|t| t.trim()).any(|t| t == "*" || t == etag),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let values: Vec<_> = request.headers().get("If-Match").collect();
+        let if_match = match values.as_slice() {
+            [] => None,
+            values => Some(values.join(",")),
+        };
+
+        Outcome::Success(IfMatch(if_match))
+    }
+}
+
+/// Wraps a response to send an `ETag` header with it.
+pub struct Tagged<R>(pub R, pub String);
+
+impl<'r, R: Responder<'r>> Responder<'r> for Tagged<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from(self.0.respond_to(request)?)
+            .raw_header("ETag", self.1)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_match_compares_strong_tags() {
+        assert!(IfMatch(None).allows("\"1\""));
+        assert!(IfMatch(Some("*".to_string())).allows("\"1\""));
+        assert!(IfMatch(Some("\"2\", \"1\"".to_string())).allows("\"1\""));
+        assert!(!IfMatch(Some("\"2\"".to_string())).allows("\"1\""));
+        assert!(!IfMatch(Some("W/\"1\"".to_string())).allows("\"1\""));
+    }
+}
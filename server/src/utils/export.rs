@@ -0,0 +1,218 @@
+use std::cmp::Ordering;
+use std::io::{self, Read};
+
+use chrono::{DateTime, Utc};
+use diesel::PgConnection;
+use log::error;
+use rocket::http::{Accept, ContentType};
+use uuid::Uuid;
+
+use crate::DbConn;
+use crate::models::precipitation_log::{EntryCursor, EntryFilter, PrecipitationLog, PrecipitationType, SortOrder};
+
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ExportFormat {
+    Json,
+    NdJson,
+    Csv,
+}
+
+impl ExportFormat {
+    /// Picks the format for an `Accept` header, taking the first acceptable type in order of
+    /// preference. A missing header or a wildcard gets JSON. Types with a weight of 0, or one
+    /// that isn't a number, aren't acceptable.
+    pub fn negotiate(accept: Option<&Accept>) -> Option<Self> {
+        let accept = match accept {
+            Some(a) => a,
+            None => return Some(ExportFormat::Json),
+        };
+
+        let mut media_types: Vec<_> = accept.iter().filter(|m| m.weight_or(1.0) > 0.0).collect();
+        media_types.sort_by(|a, b| b.weight_or(1.0).partial_cmp(&a.weight_or(1.0)).unwrap_or(Ordering::Equal));
+
+        media_types.into_iter().filter_map(|m| {
+            let media_type = m.media_type();
+            match (media_type.top().as_str(), media_type.sub().as_str()) {
+                ("text", "csv") => Some(ExportFormat::Csv),
+                ("application", "x-ndjson") => Some(ExportFormat::NdJson),
+                ("application", "json") | ("application", "*") | ("*", "*") => Some(ExportFormat::Json),
+                _ => None,
+            }
+        }).next()
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            ExportFormat::Json => ContentType::JSON,
+            ExportFormat::NdJson => ContentType::new("application", "x-ndjson"),
+            ExportFormat::Csv => ContentType::CSV,
+        }
+    }
+}
+
+/// One exported entry, with the fields in the order CSV columns are written.
+#[derive(Serialize)]
+pub struct ExportRow {
+    pub id: Uuid,
+    pub station_id: Uuid,
+    pub logged_at: DateTime<Utc>,
+    pub measurement: f32,
+    pub ptype: PrecipitationType,
+    pub anomaly: bool,
+    pub notes: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+}
+
+impl ExportRow {
+    pub fn transform(entry: &PrecipitationLog) -> Self {
+        Self {
+            id: entry.id,
+            station_id: entry.station_id,
+            logged_at: entry.logged_at,
+            measurement: entry.measurement,
+            ptype: entry.ptype,
+            anomaly: entry.anomaly,
+            notes: entry.notes.to_owned(),
+            created_at: entry.created_at,
+            modified_at: entry.modified_at,
+        }
+    }
+}
+
+/// A reader that serializes a user's entries on demand, fetching them a page at a time so an
+/// export never holds the whole table in memory.
+pub struct EntryExport {
+    conn: DbConn,
+    user_id: Uuid,
+    filter: EntryFilter,
+    order: SortOrder,
+    cursor: Option<EntryCursor>,
+    format: ExportFormat,
+    buffer: Vec<u8>,
+    position: usize,
+    rows_written: usize,
+    finished: bool,
+}
+
+impl EntryExport {
+    pub fn new(conn: DbConn, user_id: Uuid, filter: EntryFilter, order: SortOrder, format: ExportFormat) -> Self {
+        Self {
+            conn,
+            user_id,
+            filter,
+            order,
+            cursor: None,
+            format,
+            buffer: Vec::new(),
+            position: 0,
+            rows_written: 0,
+            finished: false,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.position = 0;
+
+        let (entries, next) = PrecipitationLog::search(
+            &self.conn as &PgConnection,
+            self.user_id,
+            &self.filter,
+            self.order,
+            self.cursor.as_ref(),
+            EXPORT_PAGE_SIZE,
+        ).map_err(|err| {
+            error!("{}", err.to_string());
+            io::Error::new(io::ErrorKind::Other, err.to_string())
+        })?;
+
+        let rows: Vec<ExportRow> = entries.iter().map(ExportRow::transform).collect();
+        match self.format {
+            ExportFormat::Json => self.write_json(&rows)?,
+            ExportFormat::NdJson => self.write_ndjson(&rows)?,
+            ExportFormat::Csv => self.write_csv(&rows)?,
+        }
+
+        self.rows_written += rows.len();
+        self.cursor = next;
+        if self.cursor.is_none() {
+            self.finished = true;
+            if self.format == ExportFormat::Json {
+                self.buffer.extend_from_slice(if self.rows_written == 0 { b"[]" } else { b"]" });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&mut self, rows: &[ExportRow]) -> io::Result<()> {
+        for (i, row) in rows.iter().enumerate() {
+            self.buffer.push(if self.rows_written + i == 0 { b'[' } else { b',' });
+            serde_json::to_writer(&mut self.buffer, row)?;
+        }
+        Ok(())
+    }
+
+    fn write_ndjson(&mut self, rows: &[ExportRow]) -> io::Result<()> {
+        for row in rows {
+            serde_json::to_writer(&mut self.buffer, row)?;
+            self.buffer.push(b'\n');
+        }
+        Ok(())
+    }
+
+    fn write_csv(&mut self, rows: &[ExportRow]) -> io::Result<()> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(self.rows_written == 0)
+            .from_writer(&mut self.buffer);
+        for row in rows {
+            writer.serialize(row)?;
+        }
+        writer.flush()
+    }
+}
+
+impl Read for EntryExport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let count = buf.len().min(self.buffer.len() - self.position);
+        buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(header: &str) -> Option<ExportFormat> {
+        let accept: Accept = header.parse().unwrap();
+        ExportFormat::negotiate(Some(&accept))
+    }
+
+    #[test]
+    fn negotiates_export_format() {
+        assert_eq!(Some(ExportFormat::Json), ExportFormat::negotiate(None));
+        assert_eq!(Some(ExportFormat::Csv), negotiate("text/csv"));
+        assert_eq!(Some(ExportFormat::NdJson), negotiate("application/x-ndjson, application/json;q=0.5"));
+        assert_eq!(Some(ExportFormat::Json), negotiate("text/csv;q=0.2, */*"));
+        assert_eq!(None, negotiate("text/html"));
+    }
+
+    #[test]
+    fn ignores_unacceptable_weights() {
+        assert_eq!(Some(ExportFormat::Json), negotiate("text/csv;q=NaN, application/json"));
+        assert_eq!(Some(ExportFormat::Json), negotiate("text/csv;q=0, application/json;q=0.1"));
+        assert_eq!(None, negotiate("text/csv;q=0"));
+    }
+}
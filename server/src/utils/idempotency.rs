@@ -0,0 +1,223 @@
+use std::io::Cursor;
+
+use chrono::Duration;
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use rocket::Outcome;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, status, Responder, Response};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::idempotent_request::IdempotentRequest;
+use crate::utils::etag::Tagged;
+use crate::utils::token::hash_secret;
+
+const KEY_LIFETIME_HOURS: i64 = 24;
+const MAX_KEY_LENGTH: usize = 255;
+
+/// The request's `Idempotency-Key` header, if it sent one, and the method and path the key is
+/// tied to.
+pub struct IdempotencyKey {
+    pub key: Option<String>,
+    target: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IdempotencyKey {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let values: Vec<_> = request.headers().get("Idempotency-Key").collect();
+        let key = match values.as_slice() {
+            [] => None,
+            values => Some(values.join(",")),
+        };
+
+        Outcome::Success(IdempotencyKey {
+            key,
+            target: format!("{} {}", request.method(), request.uri()),
+        })
+    }
+}
+
+impl IdempotencyKey {
+    /// Runs `write`, unless the user already sent a request with this key in the last day, in
+    /// which case that request's response is sent again. A key can only be reused with the same
+    /// method, path and `payload`. Only successful (2xx) responses are stored, in the same
+    /// transaction as the write; any other outcome leaves the key unused so the request can be
+    /// retried once the problem is fixed.
+    pub fn run<P, R, F>(&self, conn: &PgConnection, user_id: Uuid, payload: &P, write: F) -> Result<Idempotent<R>, AppError>
+    where
+        P: Serialize,
+        R: Replayable,
+        F: FnOnce() -> Result<R, AppError>,
+    {
+        let key = match self.key {
+            Some(ref key) => key,
+            None => return write().map(Idempotent::Fresh),
+        };
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return Err(AppError::BadRequest(format!("Idempotency-Key must be 1 to {} characters", MAX_KEY_LENGTH)));
+        }
+        let request_hash = self.request_hash(payload)?;
+
+        conn.transaction::<_, AppError, _>(|| {
+            match IdempotentRequest::claim(conn, user_id, key, &request_hash, Duration::hours(KEY_LIFETIME_HOURS))? {
+                Some(earlier) if earlier.request_hash != request_hash => {
+                    Err(AppError::Conflict("This Idempotency-Key was already used for a different request".to_string()))
+                }
+                Some(earlier) => match earlier.status {
+                    Some(code) => Ok(Idempotent::Replayed(StoredResponse {
+                        status: code as u16,
+                        body: earlier.body,
+                        etag: earlier.etag,
+                    })),
+                    None => Err(AppError::Conflict("A request with this Idempotency-Key is still in progress".to_string())),
+                },
+                None => {
+                    let response = write()?;
+                    let stored = response.to_stored()?;
+                    if stored.is_success() {
+                        IdempotentRequest::complete(conn, user_id, key, i32::from(stored.status), stored.body, stored.etag)?;
+                    } else {
+                        IdempotentRequest::release(conn, user_id, key)?;
+                    }
+                    Ok(Idempotent::Fresh(response))
+                }
+            }
+        })
+    }
+
+    fn request_hash<P: Serialize>(&self, payload: &P) -> Result<String, AppError> {
+        let body = serde_json::to_string(payload).map_err(|e| AppError::Internal(e.to_string()))?;
+        Ok(hash_secret(&format!("{}\n{}", self.target, body)))
+    }
+}
+
+/// A response as it's kept for replaying.
+pub struct StoredResponse {
+    pub status: u16,
+    pub body: Option<String>,
+    pub etag: Option<String>,
+}
+
+impl StoredResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+impl<'r> Responder<'r> for StoredResponse {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        let mut response = Response::build();
+        response
+            .status(Status::from_code(self.status).unwrap_or(Status::Ok))
+            .raw_header("Idempotent-Replayed", "true");
+        if let Some(body) = self.body {
+            response.header(ContentType::JSON).sized_body(Cursor::new(body));
+        }
+        if let Some(etag) = self.etag {
+            response.raw_header("ETag", etag);
+        }
+        response.ok()
+    }
+}
+
+/// What a route using `IdempotencyKey::run` responds with: its own response the first time, the
+/// stored one for repeats. Replays carry an `Idempotent-Replayed` header.
+pub enum Idempotent<R> {
+    Fresh(R),
+    Replayed(StoredResponse),
+}
+
+impl<'r, R: Responder<'r>> Responder<'r> for Idempotent<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            Idempotent::Fresh(response) => response.respond_to(request),
+            Idempotent::Replayed(stored) => stored.respond_to(request),
+        }
+    }
+}
+
+/// Responses that can be stored and replayed.
+pub trait Replayable {
+    fn to_stored(&self) -> Result<StoredResponse, AppError>;
+}
+
+impl Replayable for Status {
+    fn to_stored(&self) -> Result<StoredResponse, AppError> {
+        Ok(StoredResponse {
+            status: self.code,
+            body: None,
+            etag: None,
+        })
+    }
+}
+
+impl<T: Serialize> Replayable for Json<T> {
+    fn to_stored(&self) -> Result<StoredResponse, AppError> {
+        let body = serde_json::to_string(&self.0).map_err(|e| AppError::Internal(e.to_string()))?;
+        Ok(StoredResponse {
+            status: Status::Ok.code,
+            body: Some(body),
+            etag: None,
+        })
+    }
+}
+
+impl<R: Replayable> Replayable for Tagged<R> {
+    fn to_stored(&self) -> Result<StoredResponse, AppError> {
+        let mut stored = self.0.to_stored()?;
+        stored.etag = Some(self.1.clone());
+        Ok(stored)
+    }
+}
+
+impl<R: Replayable> Replayable for status::Custom<R> {
+    fn to_stored(&self) -> Result<StoredResponse, AppError> {
+        let mut stored = self.1.to_stored()?;
+        stored.status = self.0.code;
+        Ok(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(target: &str) -> IdempotencyKey {
+        IdempotencyKey {
+            key: Some("retry-1".to_string()),
+            target: target.to_string(),
+        }
+    }
+
+    #[test]
+    fn request_hash_covers_target_and_payload() {
+        let post = key("POST /api/logs/entry");
+        let payload = serde_json::json!({"measurement": 1.5});
+        assert_eq!(post.request_hash(&payload).unwrap(), post.request_hash(&payload.clone()).unwrap());
+        assert_ne!(post.request_hash(&payload).unwrap(), post.request_hash(&serde_json::json!({"measurement": 2.5})).unwrap());
+        assert_ne!(post.request_hash(&payload).unwrap(), key("POST /api/logs/batch").request_hash(&payload).unwrap());
+    }
+
+    #[test]
+    fn stores_status_body_and_etag() {
+        let tagged = Tagged(Json(serde_json::json!({"id": 1})), "\"42\"".to_string()).to_stored().unwrap();
+        assert_eq!(200, tagged.status);
+        assert_eq!(Some("{\"id\":1}".to_string()), tagged.body);
+        assert_eq!(Some("\"42\"".to_string()), tagged.etag);
+        assert!(tagged.is_success());
+
+        let custom = status::Custom(Status::UnprocessableEntity, Json(vec![1, 2])).to_stored().unwrap();
+        assert_eq!(422, custom.status);
+        assert_eq!(Some("[1,2]".to_string()), custom.body);
+        assert_eq!(None, custom.etag);
+        assert!(!custom.is_success());
+
+        assert_eq!(None, Status::Ok.to_stored().unwrap().body);
+    }
+}
@@ -0,0 +1,143 @@
+use std::error::Error;
+
+use jsonwebtoken::Algorithm;
+use simple_asn1::{ASN1Block, from_der};
+
+/// A public verification key in JSON Web Key form (RFC 7517), as published at
+/// `/.well-known/jwks.json`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Jwk {
+    kty: &'static str,
+    kid: String,
+    #[serde(rename = "use")]
+    key_use: &'static str,
+    alg: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwk {
+    /// Builds the JWK for an RS256 or EdDSA public key in PEM form. RSA keys may be either
+    /// `PUBLIC KEY` (SubjectPublicKeyInfo) or `RSA PUBLIC KEY` (PKCS#1); Ed25519 keys must be
+    /// `PUBLIC KEY`.
+    pub fn from_public_pem(kid: &str, algorithm: Algorithm, public_pem: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let parsed = pem::parse(public_pem)?;
+
+        let jwk = match (algorithm, parsed.tag.as_str()) {
+            (Algorithm::RS256, "RSA PUBLIC KEY") => Self::rsa(kid, parsed.contents.as_slice())?,
+            (Algorithm::RS256, "PUBLIC KEY") => Self::rsa(kid, subject_public_key(parsed.contents.as_slice())?.as_slice())?,
+            (Algorithm::EdDSA, "PUBLIC KEY") => {
+                let x = subject_public_key(parsed.contents.as_slice())?;
+                if x.len() != 32 {
+                    return Err("not an Ed25519 public key".into());
+                }
+
+                Jwk {
+                    kty: "OKP",
+                    kid: kid.to_string(),
+                    key_use: "sig",
+                    alg: "EdDSA",
+                    n: None,
+                    e: None,
+                    crv: Some("Ed25519"),
+                    x: Some(encode(x.as_slice())),
+                }
+            }
+            (_, tag) => return Err(format!("can't publish a {:?} key from a {} PEM", algorithm, tag).into()),
+        };
+
+        Ok(jwk)
+    }
+
+    // `der` is a PKCS#1 RSAPublicKey: a sequence of the modulus and exponent.
+    fn rsa(kid: &str, der: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let blocks = from_der(der)?;
+        let (n, e) = match blocks.as_slice() {
+            [ASN1Block::Sequence(_, fields)] => match fields.as_slice() {
+                [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => (n.to_bytes_be().1, e.to_bytes_be().1),
+                _ => return Err("malformed RSA public key".into()),
+            },
+            _ => return Err("malformed RSA public key".into()),
+        };
+
+        Ok(Jwk {
+            kty: "RSA",
+            kid: kid.to_string(),
+            key_use: "sig",
+            alg: "RS256",
+            n: Some(encode(n.as_slice())),
+            e: Some(encode(e.as_slice())),
+            crv: None,
+            x: None,
+        })
+    }
+}
+
+// Pulls the key bytes out of a SubjectPublicKeyInfo: a sequence of the algorithm identifier and a
+// bit string holding the key.
+fn subject_public_key(der: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let blocks = from_der(der)?;
+    match blocks.as_slice() {
+        [ASN1Block::Sequence(_, fields)] => match fields.as_slice() {
+            [ASN1Block::Sequence(_, _), ASN1Block::BitString(_, _, key)] => Ok(key.clone()),
+            _ => Err("malformed public key".into()),
+        },
+        _ => Err("malformed public key".into()),
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSA_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEArzMCfY7T28Lw4BgVqUoI
+LjW6MsEALrLewGreaIfSpJDGofW+jnjcyGz0KIBj/lKdiT2yW+buuuD3fvQQBH7/
+AoR3LQ2hKNZi8pEqGoCA5Iac1JJLjq/oC+kCO4QIzx20jRV6ylaXRHo179Fa/7wP
+/jDUCzM9wvWSU1AyJ37VYQ5L7InKTKkm1sod3O7zyGv0rDljVmMVTMgQDIyFwtkT
+sEQ//1qbAEWtIWoTr5LsxE69RS3Nd1vHuHa7exNhr0HPzfXJkUQYvcGAq6BKZAJs
+9BGS7us/kYq08n9wG3CZiUgIzk/hiYLlmK1CT9g15WGMJUS40R1T3qqUq8cLwUON
+NQIDAQAB
+-----END PUBLIC KEY-----";
+
+    const ED25519_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAHyO0i4BsnVMtTV9J7Eer1RwtcXeoBn9r+gAp3Rhs9h4=
+-----END PUBLIC KEY-----";
+
+    #[test]
+    fn rsa_public_key_to_jwk() {
+        let jwk = Jwk::from_public_pem("rsa", Algorithm::RS256, RSA_PUBLIC_KEY.as_bytes()).unwrap();
+        assert_eq!("RSA", jwk.kty);
+        assert!(jwk.n.unwrap().starts_with("rzMCfY7T28Lw4BgVqUoI"));
+        assert_eq!(Some("AQAB".to_string()), jwk.e);
+    }
+
+    #[test]
+    fn ed25519_public_key_to_jwk() {
+        let jwk = Jwk::from_public_pem("ed", Algorithm::EdDSA, ED25519_PUBLIC_KEY.as_bytes()).unwrap();
+        assert_eq!("OKP", jwk.kty);
+        assert_eq!(Some("Ed25519"), jwk.crv);
+        assert_eq!(Some("HyO0i4BsnVMtTV9J7Eer1RwtcXeoBn9r-gAp3Rhs9h4".to_string()), jwk.x);
+    }
+
+    #[test]
+    fn rejects_mismatched_algorithm() {
+        assert!(Jwk::from_public_pem("ed", Algorithm::RS256, ED25519_PUBLIC_KEY.as_bytes()).is_err());
+        assert!(Jwk::from_public_pem("hs", Algorithm::HS256, RSA_PUBLIC_KEY.as_bytes()).is_err());
+    }
+}
@@ -1 +1,11 @@
 pub mod jwt;
+pub mod jwk;
+pub mod csv_import;
+pub mod export;
+pub mod client;
+pub mod token;
+pub mod totp;
+pub mod password;
+pub mod validation;
+pub mod etag;
+pub mod idempotency;
@@ -0,0 +1,81 @@
+use std::env;
+
+// bcrypt ignores anything past this many bytes.
+const BCRYPT_MAX_BYTES: usize = 72;
+
+/// What new passwords must look like. Read from `PASSWORD_MIN_LENGTH` and
+/// `PASSWORD_MIN_CHARACTER_CLASSES` (how many of lowercase, uppercase, digits and symbols must
+/// appear). Existing passwords aren't checked at login.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_character_classes: usize,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: usize| env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default);
+
+        Self {
+            min_length: read("PASSWORD_MIN_LENGTH", 10),
+            min_character_classes: read("PASSWORD_MIN_CHARACTER_CLASSES", 1),
+        }
+    }
+
+    /// Checks a new password for the named user, returning every rule it breaks.
+    pub fn check(&self, password: &str, user_name: &str) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            problems.push(format!("must be at least {} characters", self.min_length));
+        }
+        if password.len() > BCRYPT_MAX_BYTES {
+            problems.push(format!("must be at most {} bytes", BCRYPT_MAX_BYTES));
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ];
+        if classes.iter().filter(|c| **c).count() < self.min_character_classes {
+            problems.push(format!(
+                "must mix at least {} of lowercase letters, uppercase letters, digits and symbols",
+                self.min_character_classes
+            ));
+        }
+
+        let name = user_name.trim().to_lowercase();
+        if !name.is_empty() && password.to_lowercase().contains(name.as_str()) {
+            problems.push("must not contain the user name".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enforces_policy() {
+        let policy = PasswordPolicy {
+            min_length: 10,
+            min_character_classes: 3,
+        };
+
+        assert!(policy.check("Drizzle-2026", "adam").is_ok());
+        assert_eq!(2, policy.check("short", "adam").unwrap_err().len());
+        assert_eq!(1, policy.check("alllowercaseletters", "adam").unwrap_err().len());
+        assert_eq!(1, policy.check("Adam-rain-2026", "adam").unwrap_err().len());
+        assert_eq!(1, policy.check(&"Ab1".repeat(30), "adam").unwrap_err().len());
+    }
+}
@@ -0,0 +1,36 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Generates a random 256-bit secret, hex encoded, for tokens handed to clients.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes a token secret for storage. Secrets are random and long, so a plain SHA-256 is enough
+/// and, unlike bcrypt, lets the hash be looked up directly.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_unique() {
+        let a = generate_secret();
+        let b = generate_secret();
+        assert_eq!(64, a.len());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            hash_secret("hello")
+        );
+    }
+}
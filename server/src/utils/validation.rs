@@ -0,0 +1,139 @@
+use std::env;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::error::FieldError;
+use crate::models::precipitation_log::{PrecipitationLog, PrecipitationType};
+
+/// What an entry must look like to be stored, checked on every write path. Measurements can't be
+/// negative or exceed the limit for their precipitation type, read from `ENTRY_MAX_LIQUID`,
+/// `ENTRY_MAX_FREEZING`, `ENTRY_MAX_FROZEN` and `ENTRY_MAX_UNIDENTIFIED`. `ENTRY_MAX_NOTES_LENGTH`
+/// caps notes in characters, and `ENTRY_CLOCK_SKEW_MINUTES` is how far past the server's clock
+/// `logged_at` may be, for clients whose clocks run fast.
+pub struct EntryPolicy {
+    pub max_liquid: f32,
+    pub max_freezing: f32,
+    pub max_frozen: f32,
+    pub max_unidentified: f32,
+    pub max_notes_length: usize,
+    pub clock_skew: Duration,
+}
+
+impl Default for EntryPolicy {
+    fn default() -> Self {
+        Self {
+            max_liquid: 500.0,
+            max_freezing: 250.0,
+            max_frozen: 2000.0,
+            max_unidentified: 2000.0,
+            max_notes_length: 2000,
+            clock_skew: Duration::minutes(5),
+        }
+    }
+}
+
+impl EntryPolicy {
+    pub fn from_env() -> Self {
+        fn read<T: FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let defaults = Self::default();
+        Self {
+            max_liquid: read("ENTRY_MAX_LIQUID", defaults.max_liquid),
+            max_freezing: read("ENTRY_MAX_FREEZING", defaults.max_freezing),
+            max_frozen: read("ENTRY_MAX_FROZEN", defaults.max_frozen),
+            max_unidentified: read("ENTRY_MAX_UNIDENTIFIED", defaults.max_unidentified),
+            max_notes_length: read("ENTRY_MAX_NOTES_LENGTH", defaults.max_notes_length),
+            clock_skew: Duration::minutes(read("ENTRY_CLOCK_SKEW_MINUTES", defaults.clock_skew.num_minutes())),
+        }
+    }
+
+    pub fn max_measurement(&self, ptype: PrecipitationType) -> f32 {
+        match ptype {
+            PrecipitationType::Liquid => self.max_liquid,
+            PrecipitationType::Freezing => self.max_freezing,
+            PrecipitationType::Frozen => self.max_frozen,
+            PrecipitationType::Unidentified => self.max_unidentified,
+        }
+    }
+
+    /// Checks an entry as of `now`, returning every field that breaks a rule. Whether the station
+    /// belongs to the user is up to the caller.
+    pub fn check(&self, entry: &PrecipitationLog, now: DateTime<Utc>) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let max = self.max_measurement(entry.ptype);
+        if !entry.measurement.is_finite() {
+            errors.push(FieldError::new("measurement", "must be a number"));
+        } else if entry.measurement < 0.0 {
+            errors.push(FieldError::new("measurement", "must not be negative"));
+        } else if entry.measurement > max {
+            errors.push(FieldError::new(
+                "measurement",
+                &format!("must be at most {} for {} precipitation", max, entry.ptype.as_str()),
+            ));
+        }
+
+        if entry.logged_at > now + self.clock_skew {
+            errors.push(FieldError::new("logged_at", "must not be in the future"));
+        }
+
+        let notes_length = entry.notes.as_ref().map_or(0, |n| n.chars().count());
+        if notes_length > self.max_notes_length {
+            errors.push(FieldError::new(
+                "notes",
+                &format!("must be at most {} characters", self.max_notes_length),
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn entry(measurement: f32, ptype: PrecipitationType) -> PrecipitationLog {
+        PrecipitationLog::new(Uuid::nil(), Uuid::nil(), measurement, Utc::now(), ptype, None, false)
+    }
+
+    fn fields(result: Result<(), Vec<FieldError>>) -> Vec<String> {
+        result.err().unwrap_or_default().into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn enforces_bounds_per_type() {
+        let policy = EntryPolicy::default();
+        let now = Utc::now();
+        assert!(policy.check(&entry(12.5, PrecipitationType::Liquid), now).is_ok());
+        assert!(policy.check(&entry(1000.0, PrecipitationType::Frozen), now).is_ok());
+        assert_eq!(vec!["measurement"], fields(policy.check(&entry(1000.0, PrecipitationType::Liquid), now)));
+        assert_eq!(vec!["measurement"], fields(policy.check(&entry(-0.5, PrecipitationType::Liquid), now)));
+        assert_eq!(vec!["measurement"], fields(policy.check(&entry(f32::NAN, PrecipitationType::Liquid), now)));
+    }
+
+    #[test]
+    fn rejects_future_timestamps_and_long_notes() {
+        let policy = EntryPolicy::default();
+        let now = Utc::now();
+        let mut e = entry(1.0, PrecipitationType::Liquid);
+        e.logged_at = now + Duration::minutes(1);
+        assert!(policy.check(&e, now).is_ok());
+
+        e.logged_at = now + Duration::days(365);
+        e.notes = Some("a".repeat(policy.max_notes_length + 1));
+        assert_eq!(vec!["logged_at", "notes"], fields(policy.check(&e, now)));
+    }
+}